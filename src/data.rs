@@ -1,3 +1,7 @@
+use crate::image::Image;
+
+use std::rc::Rc;
+
 #[derive(Debug, Copy, Clone)]
 pub struct Color {
     pub r: u8,
@@ -11,6 +15,80 @@ impl Color {
     pub const RED:   Color = Color { r:255, g:0,   b:0   };
     pub const GREEN: Color = Color { r:0,   g:255, b:0   };
     pub const BLUE:  Color = Color { r:0,   g:0,   b:255 };
+
+    // The colour `k` of the way from `self` to `to`, with channels left
+    // unrounded.
+    pub fn mix(self, to: Color, k: f32, space: ColorSpace) -> [f32; 3] {
+        let (a, b) = (self.channels(), to.channels());
+        let lerp = |x: f32, y: f32| x + (y - x) * k;
+        let out = match space {
+            ColorSpace::Rgb => [lerp(a[0], b[0]), lerp(a[1], b[1]), lerp(a[2], b[2])],
+            ColorSpace::Linear => {
+                let (a, b) = (a.map(to_linear), b.map(to_linear));
+                [lerp(a[0], b[0]), lerp(a[1], b[1]), lerp(a[2], b[2])].map(from_linear)
+            },
+            ColorSpace::Hsv => {
+                let (a, b) = (to_hsv(a), to_hsv(b));
+                // A grey has no hue of its own, so it takes the other end's.
+                let (ha, hb) = match (a[1] == 0.0, b[1] == 0.0) {
+                    (true, false) => (b[0], b[0]),
+                    (false, true) => (a[0], a[0]),
+                    _ => (a[0], a[0] + ((b[0] - a[0] + 0.5).rem_euclid(1.0) - 0.5)),
+                };
+                from_hsv([lerp(ha, hb).rem_euclid(1.0), lerp(a[1], b[1]), lerp(a[2], b[2])])
+            },
+        };
+        out.map(|x| x * 255.0)
+    }
+
+    // Each channel from 0 to 1.
+    fn channels(self) -> [f32; 3] {
+        [self.r, self.g, self.b].map(|c| c as f32 / 255.0)
+    }
+}
+
+// How colours are blended when animating from one to another.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorSpace {
+    // Each channel straight from one value to the other.
+    Rgb,
+    // The same, but on light intensities with the sRGB curve undone, so
+    // the colours in between don't darken.
+    Linear,
+    // Hue the short way round the colour wheel, with saturation and value
+    // moving straight.
+    Hsv,
+}
+
+fn to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn from_linear(c: f32) -> f32 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+// Hue, saturation and value, each from 0 to 1.
+fn to_hsv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let range = max - r.min(g).min(b);
+    if range == 0.0 { return [0.0, 0.0, max]; }
+    let h = if max == r {
+        (g - b) / range
+    } else if max == g {
+        (b - r) / range + 2.0
+    } else {
+        (r - g) / range + 4.0
+    };
+    [(h / 6.0).rem_euclid(1.0), range / max, max]
+}
+
+fn from_hsv([h, s, v]: [f32; 3]) -> [f32; 3] {
+    let channel = |n: f32| {
+        let k = (n + h * 6.0) % 6.0;
+        v - v * s * k.min(4.0 - k).clamp(0.0, 1.0)
+    };
+    [channel(5.0), channel(3.0), channel(1.0)]
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -24,6 +102,24 @@ impl Point3 {
     pub fn magnitude(&self) -> f32 {
         (self.x*self.x + self.y*self.y + self.z*self.z).sqrt()
     }
+
+    pub fn dot(&self, p: Point3) -> f32 {
+        self.x*p.x + self.y*p.y + self.z*p.z
+    }
+
+    pub fn cross(&self, p: Point3) -> Point3 {
+        Point3 {
+            x: self.y*p.z - self.z*p.y,
+            y: self.z*p.x - self.x*p.z,
+            z: self.x*p.y - self.y*p.x,
+        }
+    }
+
+    // The unit vector in the same direction, or zero for zero.
+    pub fn normalized(&self) -> Point3 {
+        let len = self.magnitude();
+        if len > 0.0 { *self * (1.0 / len) } else { *self }
+    }
 }
 
 impl std::ops::Mul<f32> for Point3 {
@@ -37,6 +133,28 @@ impl std::ops::Mul<f32> for Point3 {
     }
 }
 
+impl std::ops::Add<Point3> for Point3 {
+    type Output = Point3;
+    fn add(self, p: Point3) -> Self::Output {
+        Point3 {
+            x: self.x + p.x,
+            y: self.y + p.y,
+            z: self.z + p.z,
+        }
+    }
+}
+
+impl std::ops::Sub<Point3> for Point3 {
+    type Output = Point3;
+    fn sub(self, p: Point3) -> Self::Output {
+        Point3 {
+            x: self.x - p.x,
+            y: self.y - p.y,
+            z: self.z - p.z,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Point2 {
     pub x: f32,
@@ -62,4 +180,260 @@ pub struct Triangle {
     pub p1: Point3,
     pub p2: Point3,
     pub p3: Point3,
-}
\ No newline at end of file
+}
+
+// An indexed triangle mesh. The attribute streams, when present, have one
+// entry per point. Loaders call `validate` before handing a mesh out, so
+// drawing can trust the indices.
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    pub points: Vec<Point3>,
+    pub normals: Option<Vec<Point3>>,
+    pub uvs: Option<Vec<Point2>>,
+    // Per-vertex colours, used in place of the paint.
+    pub colors: Option<Vec<Color>>,
+    pub triangles: Vec<[u32; 3]>,
+    // Named runs of triangles, in order. May be empty, or leave triangles
+    // out.
+    pub groups: Vec<MeshGroup>,
+    // Looked up by name from `groups`.
+    pub materials: Vec<Material>,
+    // Of `points`, as of the last `validate`.
+    pub bounds: Bounds,
+}
+
+// An axis-aligned box. Empty meshes have a zero box at the origin.
+#[derive(Debug, Copy, Clone)]
+pub struct Bounds {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Bounds {
+    pub fn of(points: &[Point3]) -> Bounds {
+        let zero = Point3 { x: 0.0, y: 0.0, z: 0.0 };
+        let first = match points.first() {
+            Some(&p) => p,
+            None => return Bounds { min: zero, max: zero },
+        };
+        points.iter().fold(Bounds { min: first, max: first }, |b, p| Bounds {
+            min: Point3 { x: b.min.x.min(p.x), y: b.min.y.min(p.y), z: b.min.z.min(p.z) },
+            max: Point3 { x: b.max.x.max(p.x), y: b.max.y.max(p.y), z: b.max.z.max(p.z) },
+        })
+    }
+
+    pub fn size(&self) -> Point3 {
+        self.max - self.min
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MeshGroup {
+    pub name: String,
+    pub material: Option<String>,
+    // In triangles, not indices.
+    pub start: usize,
+    pub count: usize,
+}
+
+// Surface properties, as read from an MTL file. Colour channels are in [0, 1].
+// We have no lights, so only the diffuse colour, opacity and diffuse map are
+// drawn; the rest is kept for export.
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub shininess: f32,
+    pub opacity: f32,
+    pub illum: u32,
+    pub diffuse_map: Option<Rc<Image>>,
+}
+
+impl Material {
+    pub fn new(name: &str) -> Material {
+        Material {
+            name: name.to_string(),
+            ambient: [0.0; 3],
+            diffuse: [0.8; 3],
+            specular: [0.0; 3],
+            shininess: 0.0,
+            opacity: 1.0,
+            illum: 1,
+            diffuse_map: None,
+        }
+    }
+
+    // Whether the two would draw and export the same, names aside.
+    pub fn same_as(&self, other: &Material) -> bool {
+        self.ambient == other.ambient && self.diffuse == other.diffuse
+            && self.specular == other.specular && self.shininess == other.shininess
+            && self.opacity == other.opacity && self.illum == other.illum
+            && match (&self.diffuse_map, &other.diffuse_map) {
+                (None, None) => true,
+                (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+                _ => false,
+            }
+    }
+
+    pub fn diffuse_color(&self) -> Color {
+        let c = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Color { r: c(self.diffuse[0]), g: c(self.diffuse[1]), b: c(self.diffuse[2]) }
+    }
+}
+
+impl Mesh {
+    pub fn new() -> Mesh {
+        Mesh {
+            points: vec![],
+            normals: None,
+            uvs: None,
+            colors: None,
+            triangles: vec![],
+            groups: vec![],
+            materials: vec![],
+            bounds: Bounds::of(&[]),
+        }
+    }
+
+    #[inline]
+    pub fn triangle(&self, i: usize) -> [usize; 3] {
+        let [a, b, c] = self.triangles[i];
+        [a as usize, b as usize, c as usize]
+    }
+
+    // Checks that every index, stream and group fits the mesh, and updates
+    // `bounds`.
+    pub fn validate(&mut self) -> Result<(), String> {
+        let n = self.points.len();
+        if n > u32::MAX as usize {
+            return Err(format!("too many points ({})", n));
+        }
+        for (i, tri) in self.triangles.iter().enumerate() {
+            if let Some(&v) = tri.iter().find(|&&v| v as usize >= n) {
+                return Err(format!("triangle {} uses point {} out of range (have {})", i, v, n));
+            }
+        }
+        let streams = [
+            ("normals", self.normals.as_ref().map(Vec::len)),
+            ("uvs", self.uvs.as_ref().map(Vec::len)),
+            ("colors", self.colors.as_ref().map(Vec::len)),
+        ];
+        for (name, len) in streams {
+            if let Some(len) = len.filter(|&len| len != n) {
+                return Err(format!("{} {} for {} points", len, name, n));
+            }
+        }
+        for g in &self.groups {
+            if g.start + g.count > self.triangles.len() {
+                return Err(format!("group \"{}\" runs past the last triangle", g.name));
+            }
+        }
+        self.bounds = Bounds::of(&self.points);
+        Ok(())
+    }
+
+    // Adds `other`'s points and triangles to this mesh. A stream that only
+    // one side has is filled in for the other with zeros, or white for
+    // colours. A material of `other`'s named like one of ours but different
+    // is renamed, along with its groups.
+    pub fn append(&mut self, mut other: Mesh) {
+        fn merge<T: Clone>(a: &mut Option<Vec<T>>, b: Option<Vec<T>>, a_len: usize, b_len: usize, zero: T) {
+            match (a.as_mut(), b) {
+                (None, None) => {},
+                (Some(a), Some(b)) => a.extend(b),
+                (Some(a), None) => a.extend(std::iter::repeat_n(zero, b_len)),
+                (None, Some(b)) => {
+                    let mut out = vec![zero; a_len];
+                    out.extend(b);
+                    *a = Some(out);
+                },
+            }
+        }
+        let zero = Point3 { x: 0.0, y: 0.0, z: 0.0 };
+        let (len, other_len) = (self.points.len(), other.points.len());
+        merge(&mut self.normals, other.normals, len, other_len, zero);
+        merge(&mut self.uvs, other.uvs, len, other_len, Point2 { x: 0.0, y: 0.0 });
+        merge(&mut self.colors, other.colors, len, other_len, Color::WHITE);
+
+        let mut renamed = vec![];
+        for m in other.materials {
+            let name = match self.materials.iter().find(|n| n.name == m.name) {
+                None => m.name.clone(),
+                Some(n) if n.same_as(&m) => continue,
+                Some(_) => {
+                    let taken = |name: &str| self.materials.iter().any(|n| n.name == name);
+                    let name = (2..).map(|k| format!("{}_{}", m.name, k)).find(|n| !taken(n)).unwrap();
+                    renamed.push((m.name.clone(), name.clone()));
+                    name
+                },
+            };
+            self.materials.push(Material { name, ..m });
+        }
+        for g in other.groups.iter_mut() {
+            if let Some((_, to)) = renamed.iter().find(|(from, _)| g.material.as_ref() == Some(from)) {
+                g.material = Some(to.clone());
+            }
+        }
+
+        let base = len as u32;
+        let base_tri = self.triangles.len();
+        self.groups.extend(other.groups.into_iter()
+            .map(|g| MeshGroup { start: g.start + base_tri, ..g }));
+        self.points.extend(other.points);
+        self.triangles.extend(other.triangles.iter().map(|t| [t[0] + base, t[1] + base, t[2] + base]));
+        self.bounds = Bounds::of(&self.points);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p3(x: f32, y: f32, z: f32) -> Point3 {
+        Point3 { x, y, z }
+    }
+
+    fn triangle() -> Mesh {
+        let mut mesh = Mesh::new();
+        mesh.points = vec![p3(0.0, 0.0, 0.0), p3(2.0, 0.0, -1.0), p3(0.0, 3.0, 1.0)];
+        mesh.triangles = vec![[0, 1, 2]];
+        mesh
+    }
+
+    #[test]
+    fn validate_checks_indices_streams_and_groups() {
+        let mut mesh = triangle();
+        mesh.validate().unwrap();
+        let (min, max) = (mesh.bounds.min, mesh.bounds.max);
+        assert_eq!([min.x, min.y, min.z, max.x, max.y, max.z], [0.0, 0.0, -1.0, 2.0, 3.0, 1.0]);
+
+        let mut bad = triangle();
+        bad.triangles.push([0, 3, 1]);
+        assert_eq!(bad.validate().unwrap_err(), "triangle 1 uses point 3 out of range (have 3)");
+
+        let mut bad = triangle();
+        bad.uvs = Some(vec![Point2 { x: 0.0, y: 0.0 }; 2]);
+        assert_eq!(bad.validate().unwrap_err(), "2 uvs for 3 points");
+
+        let mut bad = triangle();
+        bad.groups.push(MeshGroup { name: "g".to_string(), material: None, start: 0, count: 2 });
+        assert_eq!(bad.validate().unwrap_err(), "group \"g\" runs past the last triangle");
+    }
+
+    #[test]
+    fn append_offsets_indices_and_fills_streams() {
+        let mut mesh = triangle();
+        let mut other = triangle();
+        other.colors = Some(vec![Color { r: 1, g: 2, b: 3 }; 3]);
+        other.groups.push(MeshGroup { name: "g".to_string(), material: None, start: 0, count: 1 });
+        mesh.append(other);
+        assert_eq!(mesh.triangles, [[0, 1, 2], [3, 4, 5]]);
+        assert_eq!((mesh.groups[0].start, mesh.groups[0].count), (1, 1));
+        let colors = mesh.colors.as_ref().unwrap();
+        assert_eq!((colors.len(), colors[0].r, colors[3].r), (6, 255, 1));
+        assert!(mesh.normals.is_none());
+        mesh.validate().unwrap();
+    }
+}
@@ -1,4 +1,6 @@
 use crate::data::*;
+use crate::image::{Filter, Fit, Image, Rect};
+use crate::paint::Paint;
 
 pub struct Screen {
     pub w: usize,
@@ -20,12 +22,45 @@ impl Screen {
             set_px_unsafe_index(self, Color::BLACK, i);
         }
     }
+
+    pub fn fill(&mut self, paint: &Paint) {
+        for y in 0..self.h as isize {
+            for x in 0..self.w as isize {
+                set_px_unsafe(self, paint, PointScreen { x, y });
+            }
+        }
+    }
+
+    pub fn fill_image(&mut self, image: &Image, fit: Fit) {
+        if fit == Fit::Center { self.clear(); }
+        let left = (self.w as isize - image.w as isize) / 2;
+        let top  = (self.h as isize - image.h as isize) / 2;
+        for y in 0..self.h {
+            for x in 0..self.w {
+                let (ix, iy) = match fit {
+                    Fit::Stretch => (x * image.w / self.w, y * image.h / self.h),
+                    Fit::Tile    => (x % image.w, y % image.h),
+                    Fit::Center  => {
+                        let ix = x as isize - left;
+                        let iy = y as isize - top;
+                        if ix < 0 || iy < 0 || ix >= image.w as isize || iy >= image.h as isize {
+                            continue;
+                        }
+                        (ix as usize, iy as usize)
+                    },
+                };
+                set_px_unsafe_index(self, image.get(ix, iy).0, y * self.w + x);
+            }
+        }
+    }
 }
 
 #[inline]
-fn set_px_unsafe(screen: &mut Screen, color: Color, point: PointScreen) {
+fn set_px_unsafe(screen: &mut Screen, paint: &Paint, point: PointScreen) {
     let i = (point.x + point.y * screen.w as isize) as usize;
-    set_px_unsafe_index(screen, color, i);
+    let (color, alpha) = paint.at(point.x, point.y);
+    if alpha >= 1.0 { set_px_unsafe_index(screen, color, i); }
+    else if alpha > 0.0 { blend_px_unsafe_index(screen, color, alpha, i); }
 }
 
 #[inline]
@@ -35,11 +70,20 @@ fn set_px_unsafe_index(screen: &mut Screen, color: Color, i: usize) {
     screen.data[i * 4 + 2] = color.r;
 }
 
+// Mixes `color` over the pixel at `i` with opacity `alpha` in [0, 1].
 #[inline]
-fn set_px_safe(screen: &mut Screen, color: Color, point: PointScreen) {
+fn blend_px_unsafe_index(screen: &mut Screen, color: Color, alpha: f32, i: usize) {
+    let mix = |dst: u8, src: u8| (dst as f32 + (src as f32 - dst as f32) * alpha) as u8;
+    screen.data[i * 4    ] = mix(screen.data[i * 4    ], color.b);
+    screen.data[i * 4 + 1] = mix(screen.data[i * 4 + 1], color.g);
+    screen.data[i * 4 + 2] = mix(screen.data[i * 4 + 2], color.r);
+}
+
+#[inline]
+fn set_px_safe(screen: &mut Screen, paint: &Paint, point: PointScreen) {
     if point.x >= screen.w as isize { return; }
     if point.y >= screen.h as isize { return; }
-    set_px_unsafe(screen, color, point);
+    set_px_unsafe(screen, paint, point);
 }
 
 fn clamp<T>(x: T, min: T, max: T) -> T
@@ -49,7 +93,7 @@ where T: PartialOrd<T> {
     return x;
 }
 
-pub fn draw_point(screen: &mut Screen, point: PointScreen, r: usize, color: Color) {
+pub fn draw_point(screen: &mut Screen, point: PointScreen, r: usize, paint: &Paint) {
     let px = point.x as isize;
     let py = point.y as isize;
     let w = r as isize;
@@ -61,16 +105,28 @@ pub fn draw_point(screen: &mut Screen, point: PointScreen, r: usize, color: Colo
 
     for x in left..right+1 {
         for y in top..bot+1 {
-            set_px_safe(screen, color, PointScreen { x: x, y: y, });
+            set_px_safe(screen, paint, PointScreen { x: x, y: y, });
         }
     }
 }
 
+// Fills the rectangle with corners `p1` and `p2`, inclusive.
+pub fn fill_rect(
+    screen: &mut Screen,
+    p1: PointScreen,
+    p2: PointScreen,
+    paint: &Paint
+) {
+    for y in p1.y.min(p2.y)..p1.y.max(p2.y)+1 {
+        fill_row(screen, y, p1.x.min(p2.x), p1.x.max(p2.x), paint);
+    }
+}
+
 pub fn draw_line(
     screen: &mut Screen,
     mut p1: PointScreen,
     mut p2: PointScreen,
-    color: Color
+    paint: &Paint
 ) {
     if p2.x < p1.x {
         let tmp = p1;
@@ -86,10 +142,10 @@ pub fn draw_line(
     let mut dy: isize = y2 - y1;
 
     if dx.abs() >= dy.abs() {
-        if dx == 0 { fill_col(screen, p1.x, p1.y, p2.y, color); return }
+        if dx == 0 { fill_col(screen, p1.x, p1.y, p2.y, paint); return }
         for x in 0..dx+1 {
             let y = y1 + x * dy / dx;
-            set_px_safe(screen, color,
+            set_px_safe(screen, paint,
                 PointScreen {
                     x: (x1 + x),
                     y: y
@@ -102,10 +158,10 @@ pub fn draw_line(
             dx *= -1;
             dy *= -1;
         }
-        if dy == 0 { fill_row(screen, p1.y, p1.x, p2.x, color); return }
+        if dy == 0 { fill_row(screen, p1.y, p1.x, p2.x, paint); return }
         for y in 0..dy+1 {
             let x = x1 + y * dx / dy;
-            set_px_safe(screen, color,
+            set_px_safe(screen, paint,
                 PointScreen {
                     x: x,
                     y: (y1 + y)
@@ -119,7 +175,7 @@ pub fn draw_triangle(
     p1: PointScreen,
     p2: PointScreen,
     p3: PointScreen,
-    color: Color
+    paint: &Paint
 ) {
     let (bot, mid, top) = {
         let (p1, p2, p3) = (p1, p2, p3);
@@ -147,8 +203,125 @@ pub fn draw_triangle(
         }
     };
 
-    fill_flat_top_tri(screen, bot, mid, mid2, color);
-    fill_flat_bot_tri(screen, top, mid, mid2, color);
+    fill_flat_top_tri(screen, bot, mid, mid2, paint);
+    fill_flat_bot_tri(screen, top, mid, mid2, paint);
+}
+
+// A parallelogram on screen: the centre and the vectors along its edges.
+#[derive(Debug, Copy, Clone)]
+pub struct Quad {
+    pub center: Point2,
+    pub u: Point2,
+    pub v: Point2,
+}
+
+// Maps `rect` of `image` onto `quad`, blending by the image's alpha times
+// `opacity`.
+pub fn draw_image(
+    screen: &mut Screen,
+    image: &Image,
+    rect: Rect,
+    quad: Quad,
+    opacity: f32,
+    filter: Filter
+) {
+    let Quad { center: c, u, v } = quad;
+    let det = u.x * v.y - u.y * v.x;
+    if det.abs() < 1e-6 || rect.w == 0 || rect.h == 0 || opacity <= 0.0 { return; }
+
+    let ext_x = (u.x.abs() + v.x.abs()) / 2.0;
+    let ext_y = (u.y.abs() + v.y.abs()) / 2.0;
+    let left  = ((c.x - ext_x).floor() as isize).max(0);
+    let right = ((c.x + ext_x).ceil() as isize).min(screen.w as isize - 1);
+    let top   = ((c.y - ext_y).floor() as isize).max(0);
+    let bot   = ((c.y + ext_y).ceil() as isize).min(screen.h as isize - 1);
+
+    for y in top..bot+1 {
+        for x in left..right+1 {
+            // Solve c + s*u + t*v = p for the pixel centre p.
+            let dx = x as f32 + 0.5 - c.x;
+            let dy = y as f32 + 0.5 - c.y;
+            let s = (dx * v.y - dy * v.x) / det + 0.5;
+            let t = (u.x * dy - u.y * dx) / det + 0.5;
+            if !(0.0..1.0).contains(&s) || !(0.0..1.0).contains(&t) { continue; }
+
+            let (color, alpha) = image.sample(rect, s, t, filter);
+            let alpha = alpha as f32 / 255.0 * opacity.min(1.0);
+            if alpha <= 0.0 { continue; }
+            blend_px_unsafe_index(screen, color, alpha, (y * screen.w as isize + x) as usize);
+        }
+    }
+}
+
+// Fills a triangle with `texture` sampled at interpolated `uvs`, multiplied
+// by `tint`. Texture coordinates wrap, and v runs up the image as in OBJ.
+// Without a texture the triangle is just `tint`.
+pub fn draw_triangle_textured(
+    screen: &mut Screen,
+    pts: [PointScreen; 3],
+    uvs: [Point2; 3],
+    texture: Option<&Image>,
+    tint: Color,
+    opacity: f32
+) {
+    if opacity <= 0.0 { return; }
+    fill_barycentric(screen, pts, |w| {
+        let (color, alpha) = match texture {
+            Some(image) => {
+                let u = w[0] * uvs[0].x + w[1] * uvs[1].x + w[2] * uvs[2].x;
+                let v = w[0] * uvs[0].y + w[1] * uvs[1].y + w[2] * uvs[2].y;
+                let full = Rect { x: 0, y: 0, w: image.w, h: image.h };
+                let (t, alpha) = image.sample(
+                    full, u.rem_euclid(1.0), 1.0 - v.rem_euclid(1.0), Filter::Nearest);
+                let m = |a: u8, b: u8| (a as u16 * b as u16 / 255) as u8;
+                (Color { r: m(t.r, tint.r), g: m(t.g, tint.g), b: m(t.b, tint.b) },
+                 alpha as f32 / 255.0)
+            },
+            None => (tint, 1.0),
+        };
+        (color, alpha * opacity.min(1.0))
+    });
+}
+
+// Fills a triangle blending smoothly between the colours at its corners.
+pub fn draw_triangle_shaded(screen: &mut Screen, pts: [PointScreen; 3], colors: [Color; 3]) {
+    fill_barycentric(screen, pts, |w| {
+        let c = |a: u8, b: u8, c: u8| (w[0] * a as f32 + w[1] * b as f32 + w[2] * c as f32)
+            .round().clamp(0.0, 255.0) as u8;
+        let [x, y, z] = colors;
+        (Color { r: c(x.r, y.r, z.r), g: c(x.g, y.g, z.g), b: c(x.b, y.b, z.b) }, 1.0)
+    });
+}
+
+// Blends `shade(weights)` into every pixel inside the triangle, where the
+// weights are the pixel's barycentric coordinates.
+fn fill_barycentric<F>(screen: &mut Screen, pts: [PointScreen; 3], mut shade: F)
+where F: FnMut([f32; 3]) -> (Color, f32) {
+    let [a, b, c] = pts;
+    let det = ((b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y)) as f32;
+    if det == 0.0 { return; }
+
+    let left  = a.x.min(b.x).min(c.x).max(0);
+    let right = a.x.max(b.x).max(c.x).min(screen.w as isize - 1);
+    let top   = a.y.min(b.y).min(c.y).max(0);
+    let bot   = a.y.max(b.y).max(c.y).min(screen.h as isize - 1);
+
+    for y in top..bot+1 {
+        for x in left..right+1 {
+            // The pixel is inside when all three weights are non-negative,
+            // whichever way the triangle winds.
+            let wb = ((x - a.x) * (c.y - a.y) - (c.x - a.x) * (y - a.y)) as f32 / det;
+            let wc = ((b.x - a.x) * (y - a.y) - (x - a.x) * (b.y - a.y)) as f32 / det;
+            let wa = 1.0 - wb - wc;
+            if wa < 0.0 || wb < 0.0 || wc < 0.0 { continue; }
+
+            let (color, alpha) = shade([wa, wb, wc]);
+            if alpha <= 0.0 { continue; }
+            let i = (y * screen.w as isize + x) as usize;
+            if alpha >= 1.0 { set_px_unsafe_index(screen, color, i); }
+            else { blend_px_unsafe_index(screen, color, alpha, i); }
+        }
+    }
 }
 
 fn fill_flat_top_tri(
@@ -156,7 +329,7 @@ fn fill_flat_top_tri(
     bot: PointScreen,
     mut mid: PointScreen,
     mut mid2: PointScreen,
-    color: Color
+    paint: &Paint
 ) {
     if mid.x > mid2.x {
         let tmp = mid;
@@ -173,7 +346,7 @@ fn fill_flat_top_tri(
     for i in 0..dy+1 {
         let lt_x = mid.x  as isize + i * dx1 / dy;
         let rt_x = mid2.x as isize + i * dx2 / dy;
-        fill_row(screen, mid.y + i, lt_x, rt_x, color);
+        fill_row(screen, mid.y + i, lt_x, rt_x, paint);
     }
 }
 
@@ -182,7 +355,7 @@ fn fill_flat_bot_tri(
     top: PointScreen,
     mut mid: PointScreen,
     mut mid2: PointScreen,
-    color: Color
+    paint: &Paint
 ) {
     if mid.x > mid2.x {
         let tmp = mid;
@@ -199,7 +372,7 @@ fn fill_flat_bot_tri(
     for i in 0..dy+1 {
         let lt_x = top.x as isize + i * dx1 / dy;
         let rt_x = top.x as isize + i * dx2 / dy;
-        fill_row(screen, top.y + i, lt_x, rt_x, color);
+        fill_row(screen, top.y + i, lt_x, rt_x, paint);
     }
 }
 
@@ -208,7 +381,7 @@ fn fill_col(
     x: isize,
     mut y1: isize,
     mut y2: isize,
-    color: Color
+    paint: &Paint
 ) {
     if x < 0  || x  >= (screen.h as isize) { return; }
     if y2 < 0 || y1 >= (screen.w as isize) { return; }
@@ -216,7 +389,7 @@ fn fill_col(
     if y2 >= (screen.h as isize) { y2 = screen.h as isize - 1; }
 
     for y in y1..y2+1 {
-        set_px_unsafe(screen, color, PointScreen { x: x, y: y });
+        set_px_unsafe(screen, paint, PointScreen { x: x, y: y });
     }
 }
 
@@ -225,7 +398,7 @@ fn fill_row(
     y: isize,
     mut x1: isize,
     mut x2: isize,
-    color: Color
+    paint: &Paint
 ) {
     if y < 0  || y  >= (screen.h as isize) { return; }
     if x2 < 0 || x1 >= (screen.w as isize) { return; }
@@ -233,6 +406,6 @@ fn fill_row(
     if x2 >= (screen.w as isize) { x2 = screen.w as isize - 1; }
 
     for x in x1..x2+1 {
-        set_px_unsafe(screen, color, PointScreen { x: x, y: y });
+        set_px_unsafe(screen, paint, PointScreen { x: x, y: y });
     }
 }
\ No newline at end of file
@@ -0,0 +1,232 @@
+use std::f32::consts::PI;
+
+// How an animation moves between its two values: a map from the fraction of
+// its time gone to the fraction of the way travelled.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Easing {
+    Linear,
+    // Holds the first value until the animation ends.
+    Step,
+    In(Curve),
+    Out(Curve),
+    InOut(Curve),
+    // A CSS timing curve from (0, 0) to (1, 1) with these control points.
+    CubicBezier(f32, f32, f32, f32),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Curve { Quad, Cubic, Expo, Back, Elastic, Bounce }
+
+impl Curve {
+    // The ease-in form; the others are built from it.
+    fn ease_in(self, p: f32) -> f32 {
+        match self {
+            Curve::Quad => p * p,
+            Curve::Cubic => p * p * p,
+            Curve::Expo => if p <= 0.0 { 0.0 } else { 2f32.powf(10.0 * p - 10.0) },
+            Curve::Back => {
+                // Overshoots by about 10%.
+                let c = 1.70158;
+                p * p * ((c + 1.0) * p - c)
+            },
+            Curve::Elastic => {
+                if p <= 0.0 || p >= 1.0 { return p.clamp(0.0, 1.0); }
+                -(2f32.powf(10.0 * p - 10.0)) * ((10.0 * p - 10.75) * 2.0 * PI / 3.0).sin()
+            },
+            Curve::Bounce => 1.0 - bounce_out(1.0 - p),
+        }
+    }
+}
+
+// Four bounces, each smaller than the last, landing at 1.
+fn bounce_out(p: f32) -> f32 {
+    let (n, d) = (7.5625, 2.75);
+    if p < 1.0 / d {
+        n * p * p
+    } else if p < 2.0 / d {
+        let p = p - 1.5 / d;
+        n * p * p + 0.75
+    } else if p < 2.5 / d {
+        let p = p - 2.25 / d;
+        n * p * p + 0.9375
+    } else {
+        let p = p - 2.625 / d;
+        n * p * p + 0.984375
+    }
+}
+
+impl Easing {
+    // Names follow CSS: linear, step, ease, ease-in, ease-out, ease-in-out,
+    // cubic-bezier(x1, y1, x2, y2), plus ease-in-quad, ease-out-bounce and
+    // the like for each curve.
+    pub fn parse(s: &str) -> Result<Easing, String> {
+        let name = s.to_lowercase();
+        if let Some(args) = name.strip_prefix("cubic-bezier(").and_then(|a| a.strip_suffix(')')) {
+            let xs = args.split(',')
+                .map(|x| x.trim().parse::<f32>().map_err(|_| format!("bad cubic-bezier value \"{}\"", x.trim())))
+                .collect::<Result<Vec<f32>, _>>()?;
+            if xs.len() != 4 {
+                return Err(format!("cubic-bezier needs 4 values, found {}", xs.len()));
+            }
+            // Outside [0, 1] the curve could double back in time.
+            if !(0.0..=1.0).contains(&xs[0]) || !(0.0..=1.0).contains(&xs[2]) {
+                return Err("cubic-bezier x values must be between 0 and 1".to_string());
+            }
+            return Ok(Easing::CubicBezier(xs[0], xs[1], xs[2], xs[3]));
+        }
+        Ok(match name.as_str() {
+            "linear" => Easing::Linear,
+            "step" => Easing::Step,
+            // The CSS keywords are shorthands for fixed curves.
+            "ease" => Easing::CubicBezier(0.25, 0.1, 0.25, 1.0),
+            "ease-in" => Easing::CubicBezier(0.42, 0.0, 1.0, 1.0),
+            "ease-out" => Easing::CubicBezier(0.0, 0.0, 0.58, 1.0),
+            "ease-in-out" => Easing::CubicBezier(0.42, 0.0, 0.58, 1.0),
+            _ => {
+                let bad = || format!("unknown easing \"{}\"", s);
+                let (kind, curve) = name.rsplit_once('-').ok_or_else(bad)?;
+                let curve = match curve {
+                    "quad" => Curve::Quad,
+                    "cubic" => Curve::Cubic,
+                    "expo" => Curve::Expo,
+                    "back" => Curve::Back,
+                    "elastic" => Curve::Elastic,
+                    "bounce" => Curve::Bounce,
+                    _ => return Err(bad()),
+                };
+                match kind {
+                    "ease-in" => Easing::In(curve),
+                    "ease-out" => Easing::Out(curve),
+                    "ease-in-out" => Easing::InOut(curve),
+                    _ => return Err(bad()),
+                }
+            },
+        })
+    }
+
+    // `p` runs from 0 to 1. Back and elastic curves go outside that range on
+    // the way.
+    pub fn apply(&self, p: f32) -> f32 {
+        match *self {
+            Easing::Linear => p,
+            Easing::Step => if p >= 1.0 { 1.0 } else { 0.0 },
+            Easing::In(c) => c.ease_in(p),
+            Easing::Out(c) => 1.0 - c.ease_in(1.0 - p),
+            Easing::InOut(c) => if p < 0.5 {
+                c.ease_in(2.0 * p) / 2.0
+            } else {
+                1.0 - c.ease_in(2.0 - 2.0 * p) / 2.0
+            },
+            Easing::CubicBezier(x1, y1, x2, y2) => cubic_bezier(x1, y1, x2, y2, p),
+        }
+    }
+}
+
+// The curve's y where its x is `x`. The x polynomial is inverted with a few
+// Newton steps, falling back to bisection where the slope is too flat, the
+// same way browsers do.
+fn cubic_bezier(x1: f32, y1: f32, x2: f32, y2: f32, x: f32) -> f32 {
+    // Each coordinate as a polynomial in the curve parameter s.
+    let poly = |a: f32, b: f32| {
+        let c = 3.0 * a;
+        let b = 3.0 * (b - a) - c;
+        (1.0 - c - b, b, c)
+    };
+    let (ax, bx, cx) = poly(x1, x2);
+    let (ay, by, cy) = poly(y1, y2);
+    let at = |(a, b, c): (f32, f32, f32), s: f32| ((a * s + b) * s + c) * s;
+    let slope = |s: f32| (3.0 * ax * s + 2.0 * bx) * s + cx;
+
+    let x = x.clamp(0.0, 1.0);
+    let epsilon = 1e-6;
+    let mut s = x;
+    for _ in 0..8 {
+        let err = at((ax, bx, cx), s) - x;
+        if err.abs() < epsilon { return at((ay, by, cy), s); }
+        let d = slope(s);
+        if d.abs() < epsilon { break; }
+        s -= err / d;
+    }
+    let (mut lo, mut hi) = (0.0, 1.0);
+    s = x;
+    while hi - lo > epsilon {
+        let guess = at((ax, bx, cx), s);
+        if (guess - x).abs() < epsilon { break; }
+        if guess < x { lo = s; } else { hi = s; }
+        s = (lo + hi) / 2.0;
+    }
+    at((ay, by, cy), s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    const ALL: [&str; 25] = [
+        "linear", "step", "ease", "ease-in", "ease-out", "ease-in-out", "cubic-bezier(0.1, 0.7, 1.0, 0.1)",
+        "ease-in-quad", "ease-out-quad", "ease-in-out-quad",
+        "ease-in-cubic", "ease-out-cubic", "ease-in-out-cubic",
+        "ease-in-expo", "ease-out-expo", "ease-in-out-expo",
+        "ease-in-back", "ease-out-back", "ease-in-out-back",
+        "ease-in-elastic", "ease-out-elastic", "ease-in-out-elastic",
+        "ease-in-bounce", "ease-out-bounce", "ease-in-out-bounce",
+    ];
+
+    #[test]
+    fn every_easing_starts_at_0_and_ends_at_1() {
+        for name in ALL {
+            let e = Easing::parse(name).unwrap();
+            assert!(near(e.apply(0.0), 0.0), "{} at 0 is {}", name, e.apply(0.0));
+            assert!(near(e.apply(1.0), 1.0), "{} at 1 is {}", name, e.apply(1.0));
+        }
+        assert_eq!(Easing::parse("step").unwrap().apply(0.99), 0.0);
+    }
+
+    #[test]
+    fn css_keywords_match_browsers() {
+        // Worked out by bisecting the curves to double precision.
+        let samples = [
+            ("ease", [(0.1, 0.094796), (0.25, 0.408511), (0.5, 0.802403), (0.75, 0.960459), (0.9, 0.994316)]),
+            ("ease-in", [(0.1, 0.017027), (0.25, 0.093465), (0.5, 0.315357), (0.75, 0.621862), (0.9, 0.839428)]),
+            ("ease-in-out", [(0.1, 0.019722), (0.25, 0.129162), (0.5, 0.5), (0.75, 0.870838), (0.9, 0.980278)]),
+        ];
+        for (name, points) in samples {
+            let e = Easing::parse(name).unwrap();
+            for (x, y) in points {
+                assert!(near(e.apply(x), y), "{} at {} is {}, not {}", name, x, e.apply(x), y);
+            }
+        }
+        // ease-out mirrors ease-in.
+        let (i, o) = (Easing::parse("ease-in").unwrap(), Easing::parse("ease-out").unwrap());
+        for x in [0.1, 0.3, 0.5, 0.8] {
+            assert!(near(o.apply(x), 1.0 - i.apply(1.0 - x)));
+        }
+    }
+
+    #[test]
+    fn curves() {
+        let at = |name: &str, p: f32| Easing::parse(name).unwrap().apply(p);
+        assert!(near(at("ease-in-quad", 0.5), 0.25));
+        assert!(near(at("ease-out-quad", 0.5), 0.75));
+        assert!(near(at("ease-in-out-cubic", 0.25), 0.0625));
+        assert!(near(at("ease-in-out-cubic", 0.5), 0.5));
+        assert!(near(at("ease-out-bounce", 0.5), 0.765625));
+        assert!(near(at("ease-in-elastic", 0.5), -0.015625));
+        assert!(near(at("ease-out-elastic", 0.5), 1.015625));
+        // Back dips about 10% below the start on its way.
+        let lowest = (0..100).map(|i| at("ease-in-back", i as f32 / 100.0)).fold(0.0, f32::min);
+        assert!(lowest < -0.09 && lowest > -0.11, "{}", lowest);
+    }
+
+    #[test]
+    fn rejects_bad_names() {
+        assert_eq!(Easing::parse("ease-sideways").unwrap_err(), "unknown easing \"ease-sideways\"");
+        assert_eq!(Easing::parse("ease-in-wobble").unwrap_err(), "unknown easing \"ease-in-wobble\"");
+        assert_eq!(Easing::parse("cubic-bezier(0, 1, 2)").unwrap_err(), "cubic-bezier needs 4 values, found 3");
+        assert!(Easing::parse("cubic-bezier(0, 0, 1.5, 1)").is_err());
+    }
+}
@@ -0,0 +1,120 @@
+// Problems found while loading a scene file, each pointing at the text it's
+// about, and shown the way rustc shows its errors:
+//
+//   error: expected 3 values, found 4
+//    --> scenes/test.scn:7:7
+//     |
+//   7 | COLOR 255 0 0 0
+//     |       ^^^^^^^^^
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    // The file couldn't be opened or read.
+    Io(String),
+    // The line doesn't start with a command we know.
+    UnknownCommand(String),
+    // The command is known, but what follows it is wrong.
+    Invalid(String),
+}
+
+impl From<String> for ErrorKind {
+    fn from(msg: String) -> ErrorKind {
+        ErrorKind::Invalid(msg)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Io(msg) | ErrorKind::Invalid(msg) => write!(f, "{}", msg),
+            ErrorKind::UnknownCommand(cmd) => write!(f, "unknown command \"{}\"", cmd),
+        }
+    }
+}
+
+// Part of one line of a file. Lines and columns count from 1, and columns
+// are in characters. Line 0 means the file as a whole.
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ErrorKind,
+    pub span: Span,
+    // The text of the line the span is in.
+    pub source: String,
+    // Where else the error came from, such as the USE of a macro.
+    pub notes: Vec<String>,
+}
+
+impl ParseError {
+    // An error about the whole of `file`.
+    pub fn file(file: &str, kind: ErrorKind) -> ParseError {
+        ParseError {
+            kind,
+            span: Span { file: file.to_string(), line: 0, col: 0, len: 0 },
+            source: String::new(),
+            notes: vec![],
+        }
+    }
+
+    // An error about the bytes from `start` to `end` of `source`, which is
+    // line `line` of `file`.
+    pub fn at(kind: ErrorKind, file: &str, line: usize, source: &str, start: usize, end: usize) -> ParseError {
+        let col = source[..start].chars().count() + 1;
+        let len = source[start..end].chars().count().max(1);
+        ParseError {
+            kind,
+            span: Span { file: file.to_string(), line, col, len },
+            source: source.to_string(),
+            notes: vec![],
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Span { file, line, col, len } = &self.span;
+        // Messages can carry notes on the lines after the first.
+        let kind = self.kind.to_string();
+        let mut msg = kind.lines();
+        writeln!(f, "error: {}", msg.next().unwrap_or(""))?;
+        if *line == 0 {
+            writeln!(f, " --> {}", file)?;
+        } else {
+            let gutter = " ".repeat(line.to_string().len());
+            writeln!(f, "{}--> {}:{}:{}", gutter, file, line, col)?;
+            writeln!(f, "{} |", gutter)?;
+            // Tabs would throw the caret out of line.
+            writeln!(f, "{} | {}", line, self.source.replace('\t', " "))?;
+            writeln!(f, "{} | {}{}", gutter, " ".repeat(col - 1), "^".repeat(*len))?;
+        }
+        for note in msg.chain(self.notes.iter().map(|n| n.as_str())) {
+            writeln!(f, "  = {}", note.trim())?;
+        }
+        Ok(())
+    }
+}
+
+// Everything wrong with a scene file, in the order found.
+#[derive(Debug)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for e in &self.0 {
+            writeln!(f, "{}", e)?;
+        }
+        match self.0.len() {
+            1 => write!(f, "error: could not load the scene"),
+            n => write!(f, "error: could not load the scene due to {} errors", n),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use crate::data::*;
+use crate::parser::{Command, Scene};
+use crate::obj;
+use crate::ply;
+use crate::stl;
+use crate::transform::Transform;
+
+use std::path::Path;
+
+// The scene's meshes at time `t`, in order, each with the transform it is
+// drawn with applied.
+pub fn scene_meshes(scene: &Scene, t: f32) -> Result<Vec<Mesh>, String> {
+    let mut meshes = vec![];
+    collect_meshes(scene, &scene.commands, t, Transform::IDENTITY, &mut meshes)?;
+    Ok(meshes)
+}
+
+// Adds the meshes of `commands` to `meshes`. Their own transforms apply
+// first and `outer` after, as when they're drawn.
+fn collect_meshes(
+    scene: &Scene,
+    commands: &[Command],
+    t: f32,
+    outer: Transform,
+    meshes: &mut Vec<Mesh>,
+) -> Result<(), String> {
+    let mut local = Transform::IDENTITY;
+    let mut saved = vec![];
+    for cmd in commands {
+        let tr = outer * local;
+        match cmd {
+            Command::Mesh(lods) => meshes.push(transformed(lods.full(), tr)),
+            Command::Model(model) => {
+                for (local, mesh) in model.instances() {
+                    meshes.push(transformed(mesh, tr * local));
+                }
+            },
+            Command::Group { scope, commands, .. } =>
+                scene.in_scope(*scope, || collect_meshes(scene, commands, t, tr, meshes))?,
+            Command::Push => saved.push(local),
+            Command::Pop => local = saved.pop().expect("the loader pairs every POP with a PUSH before it"),
+            _ => local = cmd.transform(local, t, scene)?,
+        }
+    }
+    Ok(())
+}
+
+pub fn transformed(mesh: &Mesh, tr: Transform) -> Mesh {
+    let mut out = mesh.clone();
+    for p in out.points.iter_mut() { *p = tr * *p; }
+    for n in out.normals.iter_mut().flatten() { *n = tr.normal(*n); }
+    // A mirroring transform turns the triangles inside out; swap two corners
+    // of each to keep them facing outwards.
+    if tr.determinant() < 0.0 {
+        for tri in out.triangles.iter_mut() { tri.swap(1, 2); }
+    }
+    out.bounds = Bounds::of(&out.points);
+    out
+}
+
+// Writes the scene's meshes at time `t` in the format named by the path's
+// extension: .stl, .obj or .ply. They are merged into one file, or with
+// `separate`, each goes to its own file numbered from 1, so "out.obj" becomes
+// "out_1.obj", "out_2.obj" and so on.
+pub fn export(scene: &Scene, t: f32, path: &str, separate: bool) -> Result<(), String> {
+    let file = Path::new(path);
+    let ext = file.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let save: fn(&str, &Mesh) -> Result<(), String> = match ext.as_str() {
+        "stl" => stl::save,
+        "obj" => obj::save,
+        "ply" => ply::save,
+        _ => return Err(format!("can't export \"{}\": expected .stl, .obj or .ply", path)),
+    };
+
+    let meshes = scene_meshes(scene, t)?;
+    if !separate {
+        let mut merged = Mesh::new();
+        for mesh in meshes {
+            merged.append(mesh);
+        }
+        return save(path, &merged);
+    }
+    let stem = file.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    for (i, mesh) in meshes.iter().enumerate() {
+        let numbered = file.with_file_name(format!("{}_{}.{}", stem, i + 1, ext));
+        save(&numbered.to_string_lossy(), mesh)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    use std::fs;
+
+    #[test]
+    fn obj_keeps_clashing_materials_apart() {
+        let dir = std::env::temp_dir().join(format!("export_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, src: &str| fs::write(dir.join(name), src).unwrap();
+        // Two meshes, each with its own idea of "steel".
+        for (name, kd) in [("a", "1 0 0"), ("b", "0 0 1")] {
+            write(&format!("{}.mtl", name), &format!("newmtl steel\nKd {}\n", kd));
+            write(&format!("{}.obj", name), &format!(
+                "mtllib {}.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\ng {}\nusemtl steel\nf 1 2 3\n", name, name));
+        }
+        write("scene.scn", "MESH \"a.obj\"\nMESH \"b.obj\"\n");
+
+        let scene = parser::load_scene(&dir.join("scene.scn").to_string_lossy()).unwrap();
+        let out = dir.join("out.obj").to_string_lossy().into_owned();
+        export(&scene, 0.0, &out, false).unwrap();
+        let mesh = obj::load(&out).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let groups: Vec<_> = mesh.groups.iter()
+            .map(|g| (g.name.as_str(), g.material.as_deref()))
+            .collect();
+        assert_eq!(groups, [("a", Some("steel")), ("b", Some("steel_2"))]);
+        let diffuse = |name: &str| mesh.materials.iter().find(|m| m.name == name).unwrap().diffuse;
+        assert_eq!(diffuse("steel"), [1.0, 0.0, 0.0]);
+        assert_eq!(diffuse("steel_2"), [0.0, 0.0, 1.0]);
+    }
+}
@@ -0,0 +1,369 @@
+// Arithmetic on numbers, animated vars and the time `t`, as written in scene
+// files, e.g. `(sin(t * 2) * 100 + 400)`. Comparisons give 1 for true and 0
+// for false.
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Num(f32),
+    Time,
+    Var(String),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Op { Add, Sub, Mul, Div, Rem, Lt, Le, Gt, Ge, Eq, Ne }
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Func { Sin, Cos, Abs, Min, Max, Clamp, Mix, Smoothstep }
+
+impl Op {
+    fn apply(self, a: f32, b: f32) -> f32 {
+        let truth = |c: bool| if c { 1.0 } else { 0.0 };
+        match self {
+            Op::Add => a + b,
+            Op::Sub => a - b,
+            Op::Mul => a * b,
+            Op::Div => a / b,
+            Op::Rem => a % b,
+            Op::Lt => truth(a < b),
+            Op::Le => truth(a <= b),
+            Op::Gt => truth(a > b),
+            Op::Ge => truth(a >= b),
+            Op::Eq => truth(a == b),
+            Op::Ne => truth(a != b),
+        }
+    }
+}
+
+impl Func {
+    fn named(name: &str) -> Option<Func> {
+        Some(match name {
+            "sin" => Func::Sin,
+            "cos" => Func::Cos,
+            "abs" => Func::Abs,
+            "min" => Func::Min,
+            "max" => Func::Max,
+            "clamp" => Func::Clamp,
+            "mix" => Func::Mix,
+            "smoothstep" => Func::Smoothstep,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Func::Sin | Func::Cos | Func::Abs => 1,
+            Func::Min | Func::Max => 2,
+            Func::Clamp | Func::Mix | Func::Smoothstep => 3,
+        }
+    }
+
+    fn apply(self, xs: &[f32]) -> f32 {
+        match self {
+            Func::Sin => xs[0].sin(),
+            Func::Cos => xs[0].cos(),
+            Func::Abs => xs[0].abs(),
+            Func::Min => xs[0].min(xs[1]),
+            Func::Max => xs[0].max(xs[1]),
+            // Written out rather than f32::clamp, which panics when lo > hi.
+            Func::Clamp => xs[0].max(xs[1]).min(xs[2]),
+            Func::Mix => xs[0] + (xs[1] - xs[0]) * xs[2],
+            Func::Smoothstep => {
+                let k = ((xs[2] - xs[0]) / (xs[1] - xs[0])).clamp(0.0, 1.0);
+                k * k * (3.0 - 2.0 * k)
+            },
+        }
+    }
+}
+
+impl Expr {
+    // `var` looks up an animated var's value at time `t`.
+    pub fn eval(&self, t: f32, var: &dyn Fn(&str) -> Result<f32, String>) -> Result<f32, String> {
+        Ok(match self {
+            Expr::Num(x) => *x,
+            Expr::Time => t,
+            Expr::Var(name) => var(name)?,
+            Expr::Neg(e) => -e.eval(t, var)?,
+            Expr::Binary(op, a, b) => op.apply(a.eval(t, var)?, b.eval(t, var)?),
+            Expr::Call(f, args) => {
+                let xs = args.iter().map(|a| a.eval(t, var)).collect::<Result<Vec<f32>, _>>()?;
+                f.apply(&xs)
+            },
+        })
+    }
+
+    // The value, if it doesn't depend on the time or any var.
+    pub fn constant(&self) -> Option<f32> {
+        Some(match self {
+            Expr::Num(x) => *x,
+            Expr::Time | Expr::Var(_) => return None,
+            Expr::Neg(e) => -e.constant()?,
+            Expr::Binary(op, a, b) => op.apply(a.constant()?, b.constant()?),
+            Expr::Call(f, args) => {
+                let xs = args.iter().map(Expr::constant).collect::<Option<Vec<f32>>>()?;
+                f.apply(&xs)
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f32),
+    Ident(String),
+    Sym(&'static str),
+    End,
+}
+
+const SYMBOLS: [&str; 15] = [
+    "<=", ">=", "==", "!=", "<", ">", "+", "-", "*", "/", "%", "(", ")", ",", "!",
+];
+
+// Splits `src` into tokens, each with the byte range it came from.
+fn lex(src: &str) -> Result<Vec<(Token, usize, usize)>, String> {
+    let bytes = src.as_bytes();
+    let mut tokens = vec![];
+    let mut at = 0;
+    while at < bytes.len() {
+        let c = bytes[at];
+        let start = at;
+        if c.is_ascii_whitespace() {
+            at += 1;
+            continue;
+        }
+        let token = if c.is_ascii_digit() || c == b'.' {
+            while at < bytes.len() && (bytes[at].is_ascii_digit() || bytes[at] == b'.') { at += 1; }
+            // An exponent, as in 1e-3.
+            if at < bytes.len() && (bytes[at] == b'e' || bytes[at] == b'E') {
+                let mut end = at + 1;
+                if end < bytes.len() && (bytes[end] == b'+' || bytes[end] == b'-') { end += 1; }
+                if end < bytes.len() && bytes[end].is_ascii_digit() {
+                    at = end;
+                    while at < bytes.len() && bytes[at].is_ascii_digit() { at += 1; }
+                }
+            }
+            let s = &src[start..at];
+            Token::Num(s.parse().map_err(|_| caret(src, start, at, &format!("bad number \"{}\"", s)))?)
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            // Dots reach into vec3 and colour vars, as in pos.x.
+            while at < bytes.len() && (bytes[at].is_ascii_alphanumeric() || bytes[at] == b'_' || bytes[at] == b'.') {
+                at += 1;
+            }
+            Token::Ident(src[start..at].to_string())
+        } else {
+            let sym = SYMBOLS.iter().find(|s| src[at..].starts_with(*s)).ok_or_else(|| {
+                let len = src[at..].chars().next().map_or(1, char::len_utf8);
+                caret(src, at, at + len, &format!("unexpected \"{}\"", &src[at..at + len]))
+            })?;
+            // "!" only makes sense as part of "!=".
+            if *sym == "!" { return Err(caret(src, at, at + 1, "unexpected \"!\"")); }
+            at += sym.len();
+            Token::Sym(sym)
+        };
+        tokens.push((token, start, at));
+    }
+    tokens.push((Token::End, bytes.len(), bytes.len()));
+    Ok(tokens)
+}
+
+// `msg`, followed by `src` with the bytes from `start` to `end` underlined.
+pub fn caret(src: &str, start: usize, end: usize, msg: &str) -> String {
+    let col = src[..start].chars().count();
+    let len = src[start..end].chars().count().max(1);
+    format!("{}\n    {}\n    {}{}", msg, src, " ".repeat(col), "^".repeat(len))
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<(Token, usize, usize)>,
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.at].0
+    }
+
+    fn error(&self, msg: &str) -> String {
+        let (token, start, end) = &self.tokens[self.at];
+        let msg = match token {
+            Token::End => format!("{}, found the end", msg),
+            _ => format!("{}, found \"{}\"", msg, &self.src[*start..*end]),
+        };
+        caret(self.src, *start, *end, &msg)
+    }
+
+    fn eat(&mut self, sym: &str) -> bool {
+        let found = matches!(self.peek(), Token::Sym(s) if *s == sym);
+        if found { self.at += 1; }
+        found
+    }
+
+    // Binary operators from the table, all at one precedence, left to right.
+    fn binary(
+        &mut self,
+        ops: &[(&str, Op)],
+        next: fn(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut lhs = next(self)?;
+        'more: loop {
+            for &(sym, op) in ops {
+                if self.eat(sym) {
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(next(self)?));
+                    continue 'more;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.binary(&[
+            ("<=", Op::Le), (">=", Op::Ge), ("==", Op::Eq), ("!=", Op::Ne), ("<", Op::Lt), (">", Op::Gt),
+        ], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary(&[("+", Op::Add), ("-", Op::Sub)], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        self.binary(&[("*", Op::Mul), ("/", Op::Div), ("%", Op::Rem)], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("-") { return Ok(Expr::Neg(Box::new(self.unary()?))); }
+        if self.eat("+") { return self.unary(); }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let (token, start, end) = self.tokens[self.at].clone();
+        match token {
+            Token::Num(x) => {
+                self.at += 1;
+                Ok(Expr::Num(x))
+            },
+            Token::Ident(name) => {
+                self.at += 1;
+                if !self.eat("(") {
+                    return Ok(if name == "t" { Expr::Time } else { Expr::Var(name) });
+                }
+                let f = Func::named(&name).ok_or_else(|| {
+                    caret(self.src, start, end, &format!("unknown function \"{}\"", name))
+                })?;
+                let mut args = vec![];
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.comparison()?);
+                        if self.eat(")") { break; }
+                        if !self.eat(",") { return Err(self.error("expected \",\" or \")\"")); }
+                    }
+                }
+                if args.len() != f.arity() {
+                    return Err(caret(self.src, start, end, &format!(
+                        "{} takes {} argument{}, found {}",
+                        name, f.arity(), if f.arity() == 1 { "" } else { "s" }, args.len())));
+                }
+                Ok(Expr::Call(f, args))
+            },
+            Token::Sym("(") => {
+                self.at += 1;
+                let e = self.comparison()?;
+                if !self.eat(")") { return Err(self.error("expected \")\"")); }
+                Ok(e)
+            },
+            _ => Err(self.error("expected a number, var or \"(\"")),
+        }
+    }
+}
+
+pub fn parse(src: &str) -> Result<Expr, String> {
+    let mut p = Parser { src, tokens: lex(src)?, at: 0 };
+    let e = p.comparison()?;
+    if *p.peek() != Token::End { return Err(p.error("expected an operator")); }
+    Ok(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Evaluates `src` at time `t`, with `pos.x` as 10 and no other vars.
+    fn eval(src: &str, t: f32) -> f32 {
+        let var = |name: &str| match name {
+            "pos.x" => Ok(10.0),
+            _ => Err(format!("no var \"{}\"", name)),
+        };
+        parse(src).unwrap().eval(t, &var).unwrap()
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3", 0.0), 7.0);
+        assert_eq!(eval("(1 + 2) * 3", 0.0), 9.0);
+        assert_eq!(eval("10 - 4 - 3", 0.0), 3.0);
+        assert_eq!(eval("12 / 3 / 2", 0.0), 2.0);
+        assert_eq!(eval("7 % 4 * 2", 0.0), 6.0);
+        // Comparisons bind loosest and give 1 or 0.
+        assert_eq!(eval("1 + 1 == 2", 0.0), 1.0);
+        assert_eq!(eval("2 * 3 < 5", 0.0), 0.0);
+        assert_eq!(eval("t >= 2", 2.0), 1.0);
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eq!(eval("-2 * 3", 0.0), -6.0);
+        assert_eq!(eval("2 * -3", 0.0), -6.0);
+        assert_eq!(eval("--2", 0.0), 2.0);
+        assert_eq!(eval("-t + 1", 3.0), -2.0);
+        assert_eq!(eval("+4", 0.0), 4.0);
+    }
+
+    #[test]
+    fn numbers_and_idents() {
+        assert_eq!(eval("1e-3", 0.0), 0.001);
+        assert_eq!(eval("2.5E+2", 0.0), 250.0);
+        assert_eq!(eval(".5", 0.0), 0.5);
+        // An "e" with no digits after it isn't an exponent.
+        assert!(parse("2e").is_err());
+        assert_eq!(eval("pos.x * 2", 0.0), 20.0);
+        assert_eq!(eval("t", 4.0), 4.0);
+        assert!(matches!(parse("pos.x").unwrap(), Expr::Var(name) if name == "pos.x"));
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(eval("max(1, min(5, 3))", 0.0), 3.0);
+        assert_eq!(eval("clamp(7, 0, 5)", 0.0), 5.0);
+        assert_eq!(eval("mix(10, 20, 0.25)", 0.0), 12.5);
+        assert_eq!(eval("smoothstep(0, 2, 1)", 0.0), 0.5);
+        assert_eq!(eval("abs(sin(0) - 1)", 0.0), 1.0);
+        assert_eq!(parse("sin(1, 2)").unwrap_err(), "sin takes 1 argument, found 2\n    sin(1, 2)\n    ^^^");
+        assert_eq!(parse("mix(1)").unwrap_err(), "mix takes 3 arguments, found 1\n    mix(1)\n    ^^^");
+        assert_eq!(parse("tan(1)").unwrap_err(), "unknown function \"tan\"\n    tan(1)\n    ^^^");
+    }
+
+    #[test]
+    fn constant_folding() {
+        assert_eq!(parse("2 * (3 + 4)").unwrap().constant(), Some(14.0));
+        assert_eq!(parse("-max(1, 2)").unwrap().constant(), Some(-2.0));
+        assert_eq!(parse("2 * t").unwrap().constant(), None);
+        assert_eq!(parse("min(pos.x, 1)").unwrap().constant(), None);
+    }
+
+    #[test]
+    fn carets() {
+        assert_eq!(parse("1 + * 2").unwrap_err(),
+            "expected a number, var or \"(\", found \"*\"\n    1 + * 2\n        ^");
+        assert_eq!(parse("(1 + 2").unwrap_err(), "expected \")\", found the end\n    (1 + 2\n          ^");
+        assert_eq!(parse("1 2").unwrap_err(), "expected an operator, found \"2\"\n    1 2\n      ^");
+        assert_eq!(parse("1 ! 2").unwrap_err(), "unexpected \"!\"\n    1 ! 2\n      ^");
+        // Columns count characters, not bytes.
+        assert_eq!(parse("1 + é").unwrap_err(), "unexpected \"é\"\n    1 + é\n        ^");
+        assert_eq!(caret("é + xy", 5, 7, "m"), "m\n    é + xy\n        ^^");
+        assert_eq!(parse("é + 1").unwrap_err(), "unexpected \"é\"\n    é + 1\n    ^");
+    }
+}
@@ -0,0 +1,503 @@
+use crate::data::*;
+use crate::json::{self, Json};
+use crate::transform::Transform;
+
+use std::fs;
+use std::path::Path;
+
+// A glTF scene: meshes placed by a hierarchy of nodes.
+#[derive(Debug, Clone)]
+pub struct Model {
+    pub meshes: Vec<Mesh>,
+    pub nodes: Vec<Node>,
+    // The nodes at the top of the default scene.
+    pub roots: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    // Relative to the parent node.
+    pub transform: Transform,
+    pub mesh: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+impl Model {
+    // Every placed mesh with its transform relative to the model, parents
+    // before children.
+    pub fn instances(&self) -> Vec<(Transform, &Mesh)> {
+        let mut out = vec![];
+        let mut stack: Vec<(usize, Transform)> = self.roots.iter().rev()
+            .map(|&i| (i, Transform::IDENTITY))
+            .collect();
+        while let Some((i, parent)) = stack.pop() {
+            let node = &self.nodes[i];
+            let world = parent * node.transform;
+            if let Some(m) = node.mesh { out.push((world, &self.meshes[m])); }
+            stack.extend(node.children.iter().rev().map(|&c| (c, world)));
+        }
+        out
+    }
+}
+
+// Reads a .gltf file, with its buffers in other files or data URIs, or a .glb
+// container. Triangle primitives are read with their positions, normals,
+// first UV set, first colour set and base colour; other primitives, textures,
+// skins and animations are left out.
+pub fn load(path: &str) -> Result<Model, String> {
+    let bytes = fs::read(path)
+        .map_err(|_| format!("file \"{}\" does not exist", path))?;
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    parse(&bytes, dir).map_err(|e| format!("bad glTF file \"{}\": {}", path, e))
+}
+
+fn parse(bytes: &[u8], dir: &Path) -> Result<Model, String> {
+    let (src, bin) = if bytes.starts_with(b"glTF") {
+        split_glb(bytes)?
+    } else {
+        (bytes, None)
+    };
+    let src = std::str::from_utf8(src).map_err(|_| "JSON is not UTF-8".to_string())?;
+    let doc = json::parse(src)?;
+
+    let version = doc.get("asset").and_then(|a| a.get("version")).and_then(Json::as_str);
+    if !version.is_some_and(|v| v.starts_with("2.")) {
+        return Err(format!("unsupported version {}", version.unwrap_or("(none)")));
+    }
+
+    let buffers = items(&doc, "buffers").iter().enumerate()
+        .map(|(i, b)| load_buffer(b, i, bin, dir).map_err(|e| format!("buffer {}: {}", i, e)))
+        .collect::<Result<Vec<Vec<u8>>, String>>()?;
+    let materials = parse_materials(&doc);
+    let meshes = items(&doc, "meshes").iter().enumerate()
+        .map(|(i, m)| parse_mesh(&doc, &buffers, &materials, m, i)
+            .map_err(|e| format!("mesh {}: {}", i, e)))
+        .collect::<Result<Vec<Mesh>, String>>()?;
+
+    let mut nodes = vec![];
+    for (i, n) in items(&doc, "nodes").iter().enumerate() {
+        let node = parse_node(n, meshes.len()).map_err(|e| format!("node {}: {}", i, e))?;
+        nodes.push(node);
+    }
+    let roots = find_roots(&doc, &nodes)?;
+    Ok(Model { meshes, nodes, roots })
+}
+
+// Returns the JSON chunk and the binary chunk, if there is one.
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), String> {
+    let u32_at = |at: usize| bytes.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        .ok_or_else(|| "unexpected end of file".to_string());
+    if u32_at(4)? != 2 { return Err(format!("unsupported GLB version {}", u32_at(4)?)); }
+    let end = u32_at(8)?.min(bytes.len());
+
+    let (mut json, mut bin) = (None, None);
+    let mut at = 12;
+    while at + 8 <= end {
+        let (len, kind) = (u32_at(at)?, u32_at(at + 4)?);
+        let chunk = bytes.get(at + 8..at + 8 + len).ok_or("unexpected end of file")?;
+        match kind {
+            0x4e4f534a if json.is_none() => json = Some(chunk),
+            0x004e4942 if bin.is_none() => bin = Some(chunk),
+            _ => {},
+        }
+        at += 8 + len;
+    }
+    Ok((json.ok_or("missing JSON chunk")?, bin))
+}
+
+fn items<'a>(obj: &'a Json, key: &str) -> &'a [Json] {
+    obj.get(key).and_then(Json::as_array).unwrap_or(&[])
+}
+
+// An optional index field, checked against the number of things it can
+// refer to.
+fn index(obj: &Json, key: &str, len: usize) -> Result<Option<usize>, String> {
+    match obj.get(key) {
+        None => Ok(None),
+        Some(j) => match j.as_usize() {
+            Some(i) if i < len => Ok(Some(i)),
+            _ => Err(format!("bad {} index", key)),
+        },
+    }
+}
+
+// A fixed-length array of numbers, or `default` if missing.
+fn floats(obj: &Json, key: &str, default: &[f32]) -> Result<Vec<f32>, String> {
+    let j = match obj.get(key) {
+        None => return Ok(default.to_vec()),
+        Some(j) => j,
+    };
+    let xs = j.as_array()
+        .and_then(|xs| xs.iter().map(|x| x.as_f64().map(|x| x as f32)).collect::<Option<Vec<f32>>>())
+        .ok_or_else(|| format!("{} should be an array of numbers", key))?;
+    if xs.len() != default.len() {
+        return Err(format!("{} should have {} numbers, found {}", key, default.len(), xs.len()));
+    }
+    Ok(xs)
+}
+
+fn load_buffer(buffer: &Json, i: usize, bin: Option<&[u8]>, dir: &Path) -> Result<Vec<u8>, String> {
+    let len = buffer.get("byteLength").and_then(Json::as_usize).ok_or("missing byteLength")?;
+    let data = match buffer.get("uri").and_then(Json::as_str) {
+        Some(uri) if uri.starts_with("data:") => {
+            let (header, payload) = uri.split_once(',').ok_or("bad data URI")?;
+            if !header.ends_with(";base64") { return Err("data URI is not base64".to_string()); }
+            base64(payload)?
+        },
+        Some(uri) => {
+            let path = dir.join(percent_decode(uri));
+            fs::read(&path).map_err(|_| format!("file \"{}\" does not exist", path.display()))?
+        },
+        // Only the first buffer of a GLB may leave out its URI.
+        None if i == 0 => bin.ok_or("no URI and no GLB binary chunk")?.to_vec(),
+        None => return Err("missing uri".to_string()),
+    };
+    if data.len() < len {
+        return Err(format!("expected {} bytes, found {}", len, data.len()));
+    }
+    Ok(data)
+}
+
+fn base64(s: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in s.bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return Err(format!("bad base64 character '{}'", c as char)),
+        };
+        acc = acc << 6 | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => { out.push(b); i += 3; },
+            (c, _) => { out.push(c); i += 1; },
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// Reads an accessor as numbers, returning them with the number of components
+// per element. Normalized integers come out in [0, 1] or [-1, 1].
+fn read_accessor(doc: &Json, buffers: &[Vec<u8>], i: usize) -> Result<(Vec<f64>, usize), String> {
+    let accessor = &items(doc, "accessors")[i];
+    let at = |e: String| format!("accessor {}: {}", i, e);
+    if accessor.get("sparse").is_some() {
+        return Err(at("sparse accessors aren't supported".to_string()));
+    }
+    let count = accessor.get("count").and_then(Json::as_usize)
+        .ok_or_else(|| at("missing count".to_string()))?;
+    let n = match accessor.get("type").and_then(Json::as_str) {
+        Some("SCALAR") => 1,
+        Some("VEC2") => 2,
+        Some("VEC3") => 3,
+        Some("VEC4") | Some("MAT2") => 4,
+        Some("MAT3") => 9,
+        Some("MAT4") => 16,
+        _ => return Err(at("bad type".to_string())),
+    };
+    let component = accessor.get("componentType").and_then(Json::as_usize).unwrap_or(0);
+    let size = match component {
+        5120 | 5121 => 1,
+        5122 | 5123 => 2,
+        5125 | 5126 => 4,
+        _ => return Err(at(format!("bad componentType {}", component))),
+    };
+    let normalized = accessor.get("normalized").and_then(Json::as_bool).unwrap_or(false);
+
+    let too_big = || at(format!("count {} is too big for its data", count));
+    let len = count.checked_mul(n).ok_or_else(too_big)?;
+    // Without a buffer view, every element is zero. There's no data to size
+    // that against, so it can't be longer than all the buffers together.
+    let view = match index(accessor, "bufferView", items(doc, "bufferViews").len()).map_err(at)? {
+        Some(v) => &items(doc, "bufferViews")[v],
+        None => {
+            let total: usize = buffers.iter().map(Vec::len).sum();
+            if len.checked_mul(size).is_none_or(|bytes| bytes > total) { return Err(too_big()); }
+            return Ok((vec![0.0; len], n));
+        },
+    };
+    let buffer = index(view, "buffer", buffers.len()).map_err(at)?
+        .ok_or_else(|| at("buffer view has no buffer".to_string()))?;
+    let view_start = view.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+    let view_len = view.get("byteLength").and_then(Json::as_usize).unwrap_or(0);
+    let data = view_start.checked_add(view_len)
+        .and_then(|view_end| buffers[buffer].get(view_start..view_end))
+        .ok_or_else(|| at("buffer view runs past its buffer".to_string()))?;
+    let stride = view.get("byteStride").and_then(Json::as_usize).unwrap_or(size * n);
+    let start = accessor.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+    // Checked before allocating, so a bad count can't ask for more memory
+    // than the file could fill.
+    let end = match count {
+        0 => Some(start),
+        _ => (count - 1).checked_mul(stride)
+            .and_then(|x| x.checked_add(start))
+            .and_then(|x| x.checked_add(size * n)),
+    };
+    if end.is_none_or(|end| end > data.len()) {
+        return Err(at("runs past its buffer view".to_string()));
+    }
+
+    let mut out = vec![0.0; len];
+
+    for k in 0..count {
+        for c in 0..n {
+            let p = start + k * stride + c * size;
+            let b = data.get(p..p + size)
+                .ok_or_else(|| at("runs past its buffer view".to_string()))?;
+            let (x, max) = match component {
+                5120 => (b[0] as i8 as f64, 127.0),
+                5121 => (b[0] as f64, 255.0),
+                5122 => (i16::from_le_bytes([b[0], b[1]]) as f64, 32767.0),
+                5123 => (u16::from_le_bytes([b[0], b[1]]) as f64, 65535.0),
+                5125 => (u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64, 4294967295.0),
+                _ => (f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64, 1.0),
+            };
+            out[k * n + c] = if normalized { (x / max).max(-1.0) } else { x };
+        }
+    }
+    Ok((out, n))
+}
+
+// Base colours, named uniquely so mesh groups can refer to them.
+fn parse_materials(doc: &Json) -> Vec<Material> {
+    let list = items(doc, "materials");
+    let names: Vec<Option<&str>> = list.iter()
+        .map(|m| m.get("name").and_then(Json::as_str))
+        .collect();
+    list.iter().enumerate().map(|(i, m)| {
+        let name = match names[i] {
+            Some(n) if names.iter().filter(|&&o| o == Some(n)).count() == 1 => n.to_string(),
+            _ => format!("material {}", i),
+        };
+        let mut material = Material::new(&name);
+        let pbr = m.get("pbrMetallicRoughness");
+        let c = pbr.and_then(|p| floats(p, "baseColorFactor", &[1.0; 4]).ok())
+            .unwrap_or_else(|| vec![1.0; 4]);
+        material.diffuse = [c[0], c[1], c[2]];
+        // Alpha only counts when the material says to blend.
+        if m.get("alphaMode").and_then(Json::as_str) == Some("BLEND") {
+            material.opacity = c[3];
+        }
+        material
+    }).collect()
+}
+
+// Puts all of a mesh's primitives into one mesh, with a group for each.
+fn parse_mesh(
+    doc: &Json,
+    buffers: &[Vec<u8>],
+    materials: &[Material],
+    json: &Json,
+    i: usize
+) -> Result<Mesh, String> {
+    let name = json.get("name").and_then(Json::as_str)
+        .map_or_else(|| format!("mesh {}", i), str::to_string);
+    let accessors = items(doc, "accessors").len();
+    let mut mesh = Mesh::new();
+
+    for (p, prim) in items(json, "primitives").iter().enumerate() {
+        let at = |e: String| format!("primitive {}: {}", p, e);
+        let mode = prim.get("mode").and_then(Json::as_usize).unwrap_or(4);
+        // Points and lines have no triangles to draw.
+        if mode < 4 { continue; }
+        if mode > 6 { return Err(at(format!("bad mode {}", mode))); }
+
+        let attrs = prim.get("attributes").ok_or_else(|| at("missing attributes".to_string()))?;
+        // The accessor for `key`, which must have one of `types` as its
+        // number of components.
+        let attr = |key: &str, types: &[(usize, &str)]| -> Result<Option<(Vec<f64>, usize)>, String> {
+            let (xs, n) = match index(attrs, key, accessors).map_err(at)? {
+                Some(a) => read_accessor(doc, buffers, a)?,
+                None => return Ok(None),
+            };
+            if !types.iter().any(|&(m, _)| m == n) {
+                let names: Vec<&str> = types.iter().map(|&(_, name)| name).collect();
+                return Err(at(format!("{} must be {}", key, names.join(" or "))));
+            }
+            Ok(Some((xs, n)))
+        };
+        let (vec2, vec3, vec4) = ((2, "VEC2"), (3, "VEC3"), (4, "VEC4"));
+        let p3 = |v: &[f64]| Point3 { x: v[0] as f32, y: v[1] as f32, z: v[2] as f32 };
+        let mut part = Mesh::new();
+        let (positions, _) = attr("POSITION", &[vec3])?.ok_or_else(|| at("missing POSITION".to_string()))?;
+        part.points = positions.chunks(3).map(p3).collect();
+        part.normals = attr("NORMAL", &[vec3])?.map(|(xs, _)| xs.chunks(3).map(p3).collect());
+        // glTF puts v = 0 at the top of the image; we keep OBJ's convention.
+        part.uvs = attr("TEXCOORD_0", &[vec2])?.map(|(xs, _)| xs.chunks(2)
+            .map(|uv| Point2 { x: uv[0] as f32, y: 1.0 - uv[1] as f32 })
+            .collect());
+        let channel = |x: f64| (x * 255.0).round().clamp(0.0, 255.0) as u8;
+        part.colors = attr("COLOR_0", &[vec3, vec4])?.map(|(xs, n)| xs.chunks(n)
+            .map(|c| Color { r: channel(c[0]), g: channel(c[1]), b: channel(c[2]) })
+            .collect());
+
+        let indices: Vec<u32> = match index(prim, "indices", accessors).map_err(at)? {
+            Some(a) => match read_accessor(doc, buffers, a)? {
+                (xs, 1) => xs.into_iter().map(|x| x as u32).collect(),
+                _ => return Err(at("indices must be SCALAR".to_string())),
+            },
+            None => (0..part.points.len() as u32).collect(),
+        };
+        match mode {
+            4 => part.triangles.extend(indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]])),
+            // Strips flip every other triangle to keep the winding.
+            5 => for k in 0..indices.len().saturating_sub(2) {
+                let (a, b) = if k % 2 == 0 { (k, k + 1) } else { (k + 1, k) };
+                part.triangles.push([indices[a], indices[b], indices[k + 2]]);
+            },
+            _ => for k in 1..indices.len().saturating_sub(1) {
+                part.triangles.push([indices[0], indices[k], indices[k + 1]]);
+            },
+        }
+
+        let material = index(prim, "material", materials.len()).map_err(at)?.map(|m| &materials[m]);
+        part.materials.extend(material.cloned());
+        part.groups.push(MeshGroup {
+            name: name.clone(),
+            material: material.map(|m| m.name.clone()),
+            start: 0,
+            count: part.triangles.len(),
+        });
+        part.validate().map_err(at)?;
+        mesh.append(part);
+    }
+    Ok(mesh)
+}
+
+fn parse_node(json: &Json, meshes: usize) -> Result<Node, String> {
+    let transform = if json.get("matrix").is_some() {
+        let m = floats(json, "matrix", &[0.0; 16])?;
+        let mut data = [0.0; 16];
+        data.copy_from_slice(&m);
+        Transform::from_columns(data)
+    } else {
+        let t = floats(json, "translation", &[0.0; 3])?;
+        let r = floats(json, "rotation", &[0.0, 0.0, 0.0, 1.0])?;
+        let s = floats(json, "scale", &[1.0; 3])?;
+        Transform::translate(t[0], t[1], t[2])
+            * Transform::quaternion(r[0], r[1], r[2], r[3])
+            * Transform::scale(s[0], s[1], s[2])
+    };
+    let children = items(json, "children").iter()
+        .map(|c| c.as_usize().ok_or_else(|| "bad child index".to_string()))
+        .collect::<Result<Vec<usize>, String>>()?;
+    Ok(Node {
+        transform,
+        mesh: index(json, "mesh", meshes)?,
+        children,
+    })
+}
+
+// The default scene's root nodes, or every parentless node if there are no
+// scenes. Also checks that the nodes form trees.
+fn find_roots(doc: &Json, nodes: &[Node]) -> Result<Vec<usize>, String> {
+    let mut parents = vec![0; nodes.len()];
+    for (i, node) in nodes.iter().enumerate() {
+        for &c in &node.children {
+            if c >= nodes.len() { return Err(format!("node {}: bad child index", i)); }
+            parents[c] += 1;
+            if parents[c] > 1 || c == i {
+                return Err(format!("node {} has more than one parent", c));
+            }
+        }
+    }
+
+    let scenes = items(doc, "scenes");
+    let roots: Vec<usize> = if scenes.is_empty() {
+        (0..nodes.len()).filter(|&i| parents[i] == 0).collect()
+    } else {
+        let scene = index(doc, "scene", scenes.len())?.unwrap_or(0);
+        items(&scenes[scene], "nodes").iter()
+            .map(|n| n.as_usize().filter(|&n| n < nodes.len())
+                .ok_or_else(|| format!("scene {}: bad node index", scene)))
+            .collect::<Result<_, _>>()?
+    };
+
+    // Every node reachable from a root once, and no cycles.
+    let mut seen = vec![false; nodes.len()];
+    let mut stack = roots.clone();
+    while let Some(i) = stack.pop() {
+        if seen[i] { return Err(format!("node {} is reachable twice", i)); }
+        seen[i] = true;
+        stack.extend(&nodes[i].children);
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One triangle: three float positions, then three short indices.
+    const TRIANGLE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{
+            "byteLength": 42,
+            "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAABAAIA"
+        }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 6 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" },
+            { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+        "nodes": [{ "mesh": 0, "translation": [0, 0, 5] }]
+    }"#;
+
+    fn parse_str(src: &str) -> Result<Model, String> {
+        parse(src.as_bytes(), Path::new(""))
+    }
+
+    #[test]
+    fn reads_a_triangle() {
+        let model = parse_str(TRIANGLE).unwrap();
+        assert_eq!(model.meshes.len(), 1);
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.triangles, [[0, 1, 2]]);
+        assert_eq!((mesh.points[1].x, mesh.points[2].y), (1.0, 1.0));
+        assert_eq!(model.roots, [0]);
+        assert_eq!(model.instances().len(), 1);
+    }
+
+    #[test]
+    fn rejects_bad_accessors() {
+        let err = |from: &str, to: &str| parse_str(&TRIANGLE.replace(from, to)).unwrap_err();
+        assert!(err(r#""count": 3, "type": "VEC3""#, r#""count": 4, "type": "VEC3""#)
+            .contains("accessor 0: runs past its buffer view"));
+        assert!(err(r#""count": 3, "type": "VEC3""#, r#""count": 18446744073709549568, "type": "VEC3""#)
+            .contains("is too big for its data"));
+        assert!(err(r#""byteOffset": 36, "byteLength": 6"#, r#""byteOffset": 18446744073709549568, "byteLength": 18446744073709549568"#)
+            .contains("buffer view runs past its buffer"));
+        assert!(err(r#""type": "VEC3""#, r#""type": "VEC2""#)
+            .contains("POSITION must be VEC3"));
+        assert!(err(r#""count": 3, "type": "SCALAR""#, r#""count": 1, "type": "VEC3""#)
+            .contains("indices must be SCALAR"));
+        assert!(err(r#""version": "2.0""#, r#""version": "1.0""#)
+            .contains("unsupported version 1.0"));
+    }
+}
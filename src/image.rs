@@ -0,0 +1,336 @@
+use crate::data::*;
+
+use std::fs;
+
+// An RGBA raster, stored row-major from the top-left corner.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub w: usize,
+    pub h: usize,
+    data: Vec<u8>,
+}
+
+// A sub-rectangle of an image, in pixels.
+#[derive(Debug, Copy, Clone)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter { Nearest, Bilinear }
+
+// How an image is laid out over a larger area.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Fit { Stretch, Tile, Center }
+
+impl Image {
+    pub fn new(w: usize, h: usize) -> Image {
+        Image { w, h, data: vec![0; w * h * 4] }
+    }
+
+    // Loads a BMP (24 or 32 bit, uncompressed or bitfields) or a Netpbm
+    // image (P2, P3, P5, P6 or P7), picked by the file's magic number.
+    pub fn load(path: &str) -> Result<Image, String> {
+        let bytes = fs::read(path)
+            .map_err(|_| format!("file \"{}\" does not exist", path))?;
+        let image = match bytes.get(..2) {
+            Some(b"BM") => parse_bmp(&bytes),
+            Some([b'P', b'2' | b'3' | b'5' | b'6' | b'7']) => parse_netpbm(&bytes),
+            _ => Err("unknown image format".to_string()),
+        };
+        image.map_err(|e| format!("bad image \"{}\": {}", path, e))
+    }
+
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> (Color, u8) {
+        let i = (y * self.w + x) * 4;
+        let d = &self.data[i..i + 4];
+        (Color { r: d[0], g: d[1], b: d[2] }, d[3])
+    }
+
+    // Samples `rect` at `(u, v)`, each in [0, 1] across it. Bilinear samples
+    // are clamped to the edges of `rect`, so neighbouring cells of a sprite
+    // sheet don't bleed into each other.
+    pub fn sample(&self, rect: Rect, u: f32, v: f32, filter: Filter) -> (Color, u8) {
+        let x = u * rect.w as f32;
+        let y = v * rect.h as f32;
+        if filter == Filter::Nearest {
+            let x = (x as usize).min(rect.w - 1);
+            let y = (y as usize).min(rect.h - 1);
+            return self.get(rect.x + x, rect.y + y);
+        }
+
+        // Pixel centres are at .5.
+        let x = (x - 0.5).clamp(0.0, (rect.w - 1) as f32);
+        let y = (y - 0.5).clamp(0.0, (rect.h - 1) as f32);
+        let (x0, y0) = (x as usize, y as usize);
+        let (x1, y1) = ((x0 + 1).min(rect.w - 1), (y0 + 1).min(rect.h - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+        let mut out = [0.0; 4];
+        for &(px, py, w) in &[
+            (x0, y0, (1.0 - fx) * (1.0 - fy)),
+            (x1, y0, fx * (1.0 - fy)),
+            (x0, y1, (1.0 - fx) * fy),
+            (x1, y1, fx * fy),
+        ] {
+            let i = ((rect.y + py) * self.w + rect.x + px) * 4;
+            for (c, o) in out.iter_mut().enumerate() {
+                *o += self.data[i + c] as f32 * w;
+            }
+        }
+        let b = |f: f32| f.round() as u8;
+        (Color { r: b(out[0]), g: b(out[1]), b: b(out[2]) }, b(out[3]))
+    }
+
+    #[inline]
+    pub fn set(&mut self, x: usize, y: usize, color: Color, alpha: u8) {
+        let i = (y * self.w + x) * 4;
+        self.data[i..i + 4].copy_from_slice(&[color.r, color.g, color.b, alpha]);
+    }
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Result<u16, String> {
+    bytes.get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| "unexpected end of file".to_string())
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<u32, String> {
+    bytes.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| "unexpected end of file".to_string())
+}
+
+fn parse_bmp(bytes: &[u8]) -> Result<Image, String> {
+    let offset = read_u32(bytes, 10)? as usize;
+    let header_size = read_u32(bytes, 14)?;
+    let w = read_u32(bytes, 18)? as i32;
+    let h = read_u32(bytes, 22)? as i32;
+    let bpp = read_u16(bytes, 28)?;
+    let compression = read_u32(bytes, 30)?;
+
+    if w <= 0 || h == 0 { return Err("empty image".to_string()); }
+    // Rows are stored bottom-up unless the height is negative.
+    let top_down = h < 0;
+    let (w, h) = (w as usize, h.unsigned_abs() as usize);
+
+    // Channel masks for R, G, B, A.
+    let masks = match (bpp, compression) {
+        (24, 0) => [0xff_0000, 0xff00, 0xff, 0],
+        (32, 0) => [0xff_0000, 0xff00, 0xff, 0],
+        (32, 3) | (32, 6) => [
+            read_u32(bytes, 54)?,
+            read_u32(bytes, 58)?,
+            read_u32(bytes, 62)?,
+            if compression == 6 || header_size >= 56 { read_u32(bytes, 66)? } else { 0 },
+        ],
+        _ => return Err(format!(
+            "unsupported format ({} bits, compression {})", bpp, compression)),
+    };
+    let channel = |px: u32, mask: u32| -> u8 {
+        if mask == 0 { return 255; }
+        let v = ((px & mask) >> mask.trailing_zeros()) as u64;
+        let max = (mask >> mask.trailing_zeros()) as u64;
+        (v * 255 / max) as u8
+    };
+
+    let px_size = bpp as usize / 8;
+    let stride = (w * px_size).div_ceil(4) * 4;
+    // Check the header against the file before allocating; the last row
+    // needn't be padded.
+    let end = (h - 1).checked_mul(stride)
+        .and_then(|n| n.checked_add(offset))
+        .and_then(|n| n.checked_add(w * px_size));
+    if end.is_none_or(|end| end > bytes.len()) {
+        return Err(format!("{}x{} pixels run past the end of the file", w, h));
+    }
+    let mut image = Image::new(w, h);
+    for row in 0..h {
+        let y = if top_down { row } else { h - 1 - row };
+        let start = offset + row * stride;
+        let line = bytes.get(start..start + w * px_size)
+            .ok_or("unexpected end of file")?;
+        for x in 0..w {
+            let p = &line[x * px_size..(x + 1) * px_size];
+            let px = if px_size == 4 {
+                u32::from_le_bytes([p[0], p[1], p[2], p[3]])
+            } else {
+                u32::from_le_bytes([p[0], p[1], p[2], 0])
+            };
+            image.set(x, y,
+                Color {
+                    r: channel(px, masks[0]),
+                    g: channel(px, masks[1]),
+                    b: channel(px, masks[2]),
+                },
+                channel(px, masks[3]));
+        }
+    }
+    Ok(image)
+}
+
+// Netpbm headers are whitespace-separated fields, with # comments.
+struct Header<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Header<'a> {
+    fn word(&mut self) -> Result<&'a str, String> {
+        loop {
+            match self.bytes.get(self.at) {
+                Some(b'#') => while self.bytes.get(self.at).is_some_and(|&c| c != b'\n') {
+                    self.at += 1;
+                },
+                Some(c) if c.is_ascii_whitespace() => self.at += 1,
+                Some(_) => break,
+                None => return Err("unexpected end of file".to_string()),
+            }
+        }
+        let start = self.at;
+        while self.bytes.get(self.at).is_some_and(|c| !c.is_ascii_whitespace()) {
+            self.at += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.at])
+            .map_err(|e| e.to_string())
+    }
+
+    fn number(&mut self) -> Result<usize, String> {
+        let word = self.word()?;
+        word.parse().map_err(|e| format!("parsing \"{}\": {}", word, e))
+    }
+}
+
+fn parse_netpbm(bytes: &[u8]) -> Result<Image, String> {
+    let mut header = Header { bytes, at: 0 };
+    let magic = header.word()?;
+
+    let (w, h, depth, maxval) = if magic == "P7" {
+        let (mut w, mut h, mut depth, mut maxval) = (0, 0, 0, 255);
+        loop {
+            match header.word()? {
+                "WIDTH"    => w = header.number()?,
+                "HEIGHT"   => h = header.number()?,
+                "DEPTH"    => depth = header.number()?,
+                "MAXVAL"   => maxval = header.number()?,
+                "TUPLTYPE" => { header.word()?; },
+                "ENDHDR"   => break,
+                word => return Err(format!("unknown PAM header field \"{}\"", word)),
+            }
+        }
+        (w, h, depth, maxval)
+    } else {
+        let w = header.number()?;
+        let h = header.number()?;
+        let maxval = header.number()?;
+        let depth = if magic == "P2" || magic == "P5" { 1 } else { 3 };
+        (w, h, depth, maxval)
+    };
+
+    if w == 0 || h == 0 { return Err("empty image".to_string()); }
+    if maxval == 0 || maxval > 65535 { return Err(format!("bad maxval {}", maxval)); }
+    if depth == 0 || depth > 4 { return Err(format!("unsupported depth {}", depth)); }
+
+    // Every sample takes at least a byte, so the header can't promise more
+    // than is left of the file.
+    let ascii = magic == "P2" || magic == "P3";
+    let size = if !ascii && maxval > 255 { 2 } else { 1 };
+    let n = w.checked_mul(h).and_then(|n| n.checked_mul(depth))
+        .filter(|n| n.checked_mul(size).is_some_and(|len| len <= bytes.len() - header.at))
+        .ok_or_else(|| format!("{}x{} pixels run past the end of the file", w, h))?;
+    let samples: Vec<usize> = if ascii {
+        (0..n).map(|_| header.number()).collect::<Result<_, _>>()?
+    } else {
+        // A single whitespace byte separates the header from the raster.
+        let start = header.at + 1;
+        let raster = bytes.get(start..start + n * size)
+            .ok_or("unexpected end of file")?;
+        if size == 2 {
+            raster.chunks(2).map(|b| (b[0] as usize) << 8 | b[1] as usize).collect()
+        } else {
+            raster.iter().map(|&b| b as usize).collect()
+        }
+    };
+
+    let scale = |s: usize| (s.min(maxval) * 255 / maxval) as u8;
+    let mut image = Image::new(w, h);
+    for (i, px) in samples.chunks(depth).enumerate() {
+        // Grayscale, grayscale + alpha, RGB or RGB + alpha.
+        let (color, alpha) = match *px {
+            [v] => (Color { r: scale(v), g: scale(v), b: scale(v) }, 255),
+            [v, a] => (Color { r: scale(v), g: scale(v), b: scale(v) }, scale(a)),
+            [r, g, b] => (Color { r: scale(r), g: scale(g), b: scale(b) }, 255),
+            [r, g, b, a] => (Color { r: scale(r), g: scale(g), b: scale(b) }, scale(a)),
+            _ => unreachable!(),
+        };
+        image.set(i % w, i / w, color, alpha);
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(image: &Image, x: usize, y: usize) -> [u8; 4] {
+        let (c, a) = image.get(x, y);
+        [c.r, c.g, c.b, a]
+    }
+
+    // A 32 bit bitfields BMP with the given size and masks, and `pixels`
+    // after the header.
+    fn bmp(w: i32, h: i32, masks: [u32; 4], pixels: &[u8]) -> Vec<u8> {
+        let mut b = vec![0; 70];
+        b[..2].copy_from_slice(b"BM");
+        b[10..14].copy_from_slice(&70u32.to_le_bytes());
+        b[14..18].copy_from_slice(&56u32.to_le_bytes());
+        b[18..22].copy_from_slice(&w.to_le_bytes());
+        b[22..26].copy_from_slice(&h.to_le_bytes());
+        b[28..30].copy_from_slice(&32u16.to_le_bytes());
+        b[30..34].copy_from_slice(&3u32.to_le_bytes());
+        for (i, m) in masks.iter().enumerate() {
+            b[54 + i*4..58 + i*4].copy_from_slice(&m.to_le_bytes());
+        }
+        b.extend_from_slice(pixels);
+        b
+    }
+
+    #[test]
+    fn reads_bmp() {
+        let masks = [0xff_0000, 0xff00, 0xff, 0xff00_0000];
+        let image = parse_bmp(&bmp(2, -1, masks, &[1, 2, 3, 4, 5, 6, 7, 8])).unwrap();
+        assert_eq!((image.w, image.h), (2, 1));
+        assert_eq!(rgba(&image, 0, 0), [3, 2, 1, 4]);
+        assert_eq!(rgba(&image, 1, 0), [7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn reads_full_width_masks() {
+        let masks = [0xffff_ffff, 0, 0, 0];
+        let image = parse_bmp(&bmp(1, 1, masks, &[0xff; 4])).unwrap();
+        assert_eq!(image.get(0, 0).0.r, 255);
+    }
+
+    #[test]
+    fn rejects_sizes_past_the_end() {
+        let masks = [0xff_0000, 0xff00, 0xff, 0];
+        assert!(parse_bmp(&bmp(60000, 60000, masks, &[0; 8])).is_err());
+        assert!(parse_bmp(&bmp(2, 2, masks, &[0; 12])).is_err());
+        assert!(parse_netpbm(b"P6 60000 60000 255\n\0\0\0").is_err());
+        assert!(parse_netpbm(b"P7\nWIDTH 4294967296\nHEIGHT 4294967296\nDEPTH 4\nENDHDR\n").is_err());
+        assert!(parse_netpbm(b"P3 2 1 255 1 2 3 4 5").is_err());
+    }
+
+    #[test]
+    fn reads_netpbm() {
+        let image = parse_netpbm(b"P3\n# two pixels\n2 1 15\n15 0 0 0 15 0\n").unwrap();
+        assert_eq!(rgba(&image, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(rgba(&image, 1, 0), [0, 255, 0, 255]);
+        let image = parse_netpbm(b"P5 1 1 65535\n\x80\x00").unwrap();
+        assert_eq!(image.get(0, 0).0.r, 127);
+    }
+}
@@ -0,0 +1,234 @@
+// Just enough JSON to read glTF.
+
+#[derive(Debug, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // In file order.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_f64().filter(|x| *x >= 0.0 && x.fract() == 0.0).map(|x| x as usize)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(xs) => Some(xs),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse(src: &str) -> Result<Json, String> {
+    let mut p = Parser { src: src.as_bytes(), at: 0, depth: 0 };
+    let value = p.value().and_then(|v| {
+        p.space();
+        if p.at < p.src.len() { Err("trailing characters".to_string()) } else { Ok(v) }
+    });
+    value.map_err(|e| {
+        let before = &src.as_bytes()[..p.at.min(src.len())];
+        let line = before.iter().filter(|&&c| c == b'\n').count() + 1;
+        let col = p.at - before.iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1) + 1;
+        format!("line {}, column {}: {}", line, col, e)
+    })
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    at: usize,
+    // Arrays and objects around the value being read.
+    depth: usize,
+}
+
+// Each level of nesting recurses, so a file of nothing but brackets would
+// otherwise overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+impl Parser<'_> {
+    fn space(&mut self) {
+        while self.src.get(self.at).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.at += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        self.space();
+        if self.src.get(self.at) != Some(&c) {
+            return Err(format!("expected '{}'", c as char));
+        }
+        self.at += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("nested more than {} deep", MAX_DEPTH));
+        }
+        self.depth += 1;
+        let value = self.nested_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn nested_value(&mut self) -> Result<Json, String> {
+        self.space();
+        match self.src.get(self.at) {
+            None => Err("unexpected end of input".to_string()),
+            Some(b'{') => {
+                self.at += 1;
+                let mut fields = vec![];
+                self.space();
+                if self.src.get(self.at) == Some(&b'}') { self.at += 1; return Ok(Json::Object(fields)); }
+                loop {
+                    self.space();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((key, self.value()?));
+                    self.space();
+                    match self.src.get(self.at) {
+                        Some(b',') => self.at += 1,
+                        Some(b'}') => { self.at += 1; return Ok(Json::Object(fields)); },
+                        _ => return Err("expected ',' or '}'".to_string()),
+                    }
+                }
+            },
+            Some(b'[') => {
+                self.at += 1;
+                let mut items = vec![];
+                self.space();
+                if self.src.get(self.at) == Some(&b']') { self.at += 1; return Ok(Json::Array(items)); }
+                loop {
+                    items.push(self.value()?);
+                    self.space();
+                    match self.src.get(self.at) {
+                        Some(b',') => self.at += 1,
+                        Some(b']') => { self.at += 1; return Ok(Json::Array(items)); },
+                        _ => return Err("expected ',' or ']'".to_string()),
+                    }
+                }
+            },
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b't') => self.word("true", Json::Bool(true)),
+            Some(b'f') => self.word("false", Json::Bool(false)),
+            Some(b'n') => self.word("null", Json::Null),
+            Some(_) => self.number(),
+        }
+    }
+
+    fn word(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if !self.src[self.at..].starts_with(word.as_bytes()) {
+            return Err("unexpected character".to_string());
+        }
+        self.at += word.len();
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.at;
+        while self.src.get(self.at)
+            .is_some_and(|c| c.is_ascii_digit() || b"+-.eE".contains(c)) {
+            self.at += 1;
+        }
+        let s = std::str::from_utf8(&self.src[start..self.at]).unwrap();
+        if s.is_empty() { return Err("unexpected character".to_string()); }
+        s.parse().map(Json::Number).map_err(|_| format!("bad number \"{}\"", s))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.src.get(self.at) != Some(&b'"') { return Err("expected a string".to_string()); }
+        self.at += 1;
+        let mut out = vec![];
+        loop {
+            match self.src.get(self.at) {
+                None => return Err("unterminated string".to_string()),
+                Some(b'"') => { self.at += 1; break; },
+                Some(b'\\') => {
+                    let c = *self.src.get(self.at + 1).ok_or("unterminated string")?;
+                    self.at += 2;
+                    let unescaped = match c {
+                        b'"' | b'\\' | b'/' => c as char,
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let hex = self.src.get(self.at..self.at + 4)
+                                .and_then(|h| std::str::from_utf8(h).ok())
+                                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                                .ok_or("bad \\u escape")?;
+                            self.at += 4;
+                            // Surrogate pairs come out as replacement characters.
+                            char::from_u32(hex).unwrap_or('\u{fffd}')
+                        },
+                        _ => return Err(format!("bad escape \\{}", c as char)),
+                    };
+                    let mut buf = [0; 4];
+                    out.extend_from_slice(unescaped.encode_utf8(&mut buf).as_bytes());
+                },
+                Some(&c) => { out.push(c); self.at += 1; },
+            }
+        }
+        String::from_utf8(out).map_err(|_| "string is not UTF-8".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_values() {
+        let doc = parse(r#"{ "a": [1, -2.5e1, true, null], "b": "x\"é" }"#).unwrap();
+        let a = doc.get("a").and_then(Json::as_array).unwrap();
+        assert_eq!(a.len(), 4);
+        assert_eq!(a[1].as_f64(), Some(-25.0));
+        assert_eq!(a[2].as_bool(), Some(true));
+        assert_eq!(doc.get("b").and_then(Json::as_str), Some("x\"é"));
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert_eq!(parse("[1, 2").unwrap_err(), "line 1, column 6: expected ',' or ']'");
+        assert_eq!(parse("{\n  \"a\" 1 }").unwrap_err(), "line 2, column 7: expected ':'");
+        assert_eq!(parse("[] x").unwrap_err(), "line 1, column 4: trailing characters");
+        // Deep enough to overflow the stack if it weren't capped.
+        let deep = "[".repeat(100_000);
+        assert!(parse(&deep).unwrap_err().contains("nested more than 128 deep"));
+        let ok = format!("{}{}", "[".repeat(100), "]".repeat(100));
+        assert!(parse(&ok).is_ok());
+    }
+}
@@ -0,0 +1,192 @@
+// Animation tracks through a list of (time, value) keys. Each key says how
+// to get from it to the next one.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interp {
+    Linear,
+    // Holds the key's value until the next key.
+    Constant,
+    // A smooth curve through the neighbouring keys.
+    CatmullRom,
+    // A curve leaving and arriving with the given slopes, in value per second.
+    Hermite { slope_in: f32, slope_out: f32 },
+    // Kochanek-Bartels: Catmull-Rom with tension, continuity and bias, each
+    // usually between -1 and 1. All zeros is plain Catmull-Rom.
+    Tcb { tension: f32, continuity: f32, bias: f32 },
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Key {
+    pub time: f32,
+    pub value: f32,
+    pub interp: Interp,
+}
+
+impl Interp {
+    // linear | constant | catmull-rom | hermite slope [slope_out] | tcb t c b
+    pub fn parse(words: &[&str]) -> Result<Interp, String> {
+        let nums = |n: usize| -> Result<Vec<f32>, String> {
+            let args = &words[1..];
+            if args.len() != n {
+                let (needs, form) = match words[0].to_lowercase().as_str() {
+                    "hermite" => ("a slope, or in and out slopes", "hermite <slope> [<slope_out>]"),
+                    "tcb" => ("tension, continuity and bias", "tcb <tension> <continuity> <bias>"),
+                    name => return Err(format!("{} takes no values, found {}", name, args.len())),
+                };
+                return Err(format!("{} needs {}, found {} value{}\nhelp: write it as {}",
+                    words[0], needs, args.len(), if args.len() == 1 { "" } else { "s" }, form));
+            }
+            args.iter()
+                .map(|s| s.parse().map_err(|e| format!("parsing \"{}\": {}", s, e)))
+                .collect()
+        };
+        Ok(match words[0].to_lowercase().as_str() {
+            "linear" => { nums(0)?; Interp::Linear },
+            "constant" => { nums(0)?; Interp::Constant },
+            "catmull-rom" => { nums(0)?; Interp::CatmullRom },
+            "hermite" => {
+                let xs = if words.len() <= 2 { nums(1)? } else { nums(2)? };
+                Interp::Hermite { slope_in: xs[0], slope_out: xs[xs.len() - 1] }
+            },
+            "tcb" => {
+                let xs = nums(3)?;
+                Interp::Tcb { tension: xs[0], continuity: xs[1], bias: xs[2] }
+            },
+            other => return Err(format!("unknown interpolation \"{}\"", other)),
+        })
+    }
+}
+
+// The slopes arriving at and leaving key `i`, in value per second. Keys
+// that aren't Hermite or TCB get Catmull-Rom slopes, so a curve meeting
+// them still flows through smoothly.
+fn slopes(keys: &[Key], i: usize) -> (f32, f32) {
+    let (tension, continuity, bias) = match keys[i].interp {
+        Interp::Hermite { slope_in, slope_out } => return (slope_in, slope_out),
+        Interp::Tcb { tension, continuity, bias } => (tension, continuity, bias),
+        _ => (0.0, 0.0, 0.0),
+    };
+    if keys.len() == 1 { return (0.0, 0.0); }
+    // The value and time spans of the segments either side. The end keys
+    // borrow the one segment they have for the missing side.
+    let segment = |j: usize| (keys[j + 1].value - keys[j].value, keys[j + 1].time - keys[j].time);
+    let (dv_prev, dt_prev) = segment(if i > 0 { i - 1 } else { i });
+    let (dv_next, dt_next) = segment(if i + 1 < keys.len() { i } else { i - 1 });
+    let (t, c, b) = (tension, continuity, bias);
+    let arriving = (1.0 - t) * ((1.0 + c) * (1.0 + b) * dv_prev + (1.0 - c) * (1.0 - b) * dv_next) / 2.0;
+    let leaving = (1.0 - t) * ((1.0 - c) * (1.0 + b) * dv_prev + (1.0 + c) * (1.0 - b) * dv_next) / 2.0;
+    // The differences above are per segment; spreading them over both
+    // segments' time keeps the curve smooth when keys aren't evenly spaced.
+    let time = dt_prev + dt_next;
+    (arriving * 2.0 / time, leaving * 2.0 / time)
+}
+
+// The track's value at `time`. Before the first key and after the last the
+// end values hold. `keys` must be sorted by time, with no two at the same
+// time.
+pub fn value_at(keys: &[Key], time: f32) -> f32 {
+    let last = keys.len() - 1;
+    if time <= keys[0].time { return keys[0].value; }
+    if time >= keys[last].time { return keys[last].value; }
+    let i = keys.partition_point(|k| k.time <= time) - 1;
+    let (a, b) = (&keys[i], &keys[i + 1]);
+    let dt = b.time - a.time;
+    let s = (time - a.time) / dt;
+    match a.interp {
+        Interp::Constant => a.value,
+        Interp::Linear => a.value + (b.value - a.value) * s,
+        _ => {
+            let m0 = slopes(keys, i).1 * dt;
+            let m1 = slopes(keys, i + 1).0 * dt;
+            let (s2, s3) = (s * s, s * s * s);
+            (2.0 * s3 - 3.0 * s2 + 1.0) * a.value
+                + (s3 - 2.0 * s2 + s) * m0
+                + (-2.0 * s3 + 3.0 * s2) * b.value
+                + (s3 - s2) * m1
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(interp: Interp) -> Vec<Key> {
+        // Unevenly spaced, so the slopes have to account for time.
+        [(0.0, 0.0), (1.0, 10.0), (3.0, 4.0), (3.5, 8.0)].iter()
+            .map(|&(time, value)| Key { time, value, interp })
+            .collect()
+    }
+
+    // The slopes just before and just after `time`.
+    fn slopes_around(keys: &[Key], time: f32) -> (f32, f32) {
+        let h = 1e-3;
+        ((value_at(keys, time) - value_at(keys, time - h)) / h,
+         (value_at(keys, time + h) - value_at(keys, time)) / h)
+    }
+
+    const SMOOTH: [Interp; 4] = [
+        Interp::Linear,
+        Interp::CatmullRom,
+        Interp::Hermite { slope_in: 3.0, slope_out: 3.0 },
+        Interp::Tcb { tension: 0.5, continuity: 0.0, bias: -0.5 },
+    ];
+
+    #[test]
+    fn passes_through_the_keys_and_holds_at_the_ends() {
+        for interp in SMOOTH.iter().chain(&[Interp::Constant]) {
+            let keys = track(*interp);
+            for k in &keys {
+                assert_eq!(value_at(&keys, k.time), k.value, "{:?}", interp);
+            }
+            assert_eq!(value_at(&keys, -1.0), 0.0);
+            assert_eq!(value_at(&keys, 9.0), 8.0);
+        }
+        let keys = track(Interp::Constant);
+        assert_eq!(value_at(&keys, 2.9), 10.0);
+        let keys = track(Interp::Linear);
+        assert_eq!(value_at(&keys, 2.0), 7.0);
+    }
+
+    #[test]
+    fn curves_are_smooth_through_the_keys() {
+        for interp in &SMOOTH[1..] {
+            let keys = track(*interp);
+            for k in &keys[1..3] {
+                let (before, after) = slopes_around(&keys, k.time);
+                assert!((before - after).abs() < 0.05, "{:?} at {}: {} then {}", interp, k.time, before, after);
+            }
+        }
+        // Plain TCB is Catmull-Rom.
+        let (tcb, cr) = (track(Interp::Tcb { tension: 0.0, continuity: 0.0, bias: 0.0 }), track(Interp::CatmullRom));
+        for time in [0.3, 1.7, 3.2] {
+            assert!((value_at(&tcb, time) - value_at(&cr, time)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn hermite_and_tcb_slopes() {
+        let keys = track(Interp::Hermite { slope_in: 2.0, slope_out: -4.0 });
+        let (before, after) = slopes_around(&keys, 1.0);
+        assert!((before - 2.0).abs() < 0.05 && (after + 4.0).abs() < 0.05, "{} then {}", before, after);
+        // Full tension stops at every key.
+        let keys = track(Interp::Tcb { tension: 1.0, continuity: 0.0, bias: 0.0 });
+        let (before, after) = slopes_around(&keys, 3.0);
+        assert!(before.abs() < 0.05 && after.abs() < 0.05);
+        // Continuity puts a corner in.
+        let keys = track(Interp::Tcb { tension: 0.0, continuity: -1.0, bias: 0.0 });
+        let (before, after) = slopes_around(&keys, 1.0);
+        assert!((before - after).abs() > 1.0);
+    }
+
+    #[test]
+    fn parses_interpolations() {
+        assert_eq!(Interp::parse(&["HERMITE", "1.5"]), Ok(Interp::Hermite { slope_in: 1.5, slope_out: 1.5 }));
+        assert_eq!(Interp::parse(&["hermite", "1", "2"]), Ok(Interp::Hermite { slope_in: 1.0, slope_out: 2.0 }));
+        assert_eq!(Interp::parse(&["tcb", "1", "0", "-1"]), Ok(Interp::Tcb { tension: 1.0, continuity: 0.0, bias: -1.0 }));
+        assert_eq!(Interp::parse(&["hermite"]).unwrap_err(),
+            "hermite needs a slope, or in and out slopes, found 0 values\nhelp: write it as hermite <slope> [<slope_out>]");
+        assert_eq!(Interp::parse(&["linear", "1"]).unwrap_err(), "linear takes no values, found 1");
+        assert_eq!(Interp::parse(&["cubic"]).unwrap_err(), "unknown interpolation \"cubic\"");
+    }
+}
@@ -0,0 +1,192 @@
+// Splitting scene file lines into words.
+//
+// Words are separated by any run of whitespace. A parenthesis keeps the word
+// going until it's closed, so `(sin(t) * 2)` and `rgb(255, 0, 0)` are one
+// word each. A string in double quotes is a word of its own, and may contain
+// \" and \\. A # starting a word comments out the rest of the line, unless
+// it's a colour like #ff8000 or #ff800080. A line ending in \ carries on on
+// the next.
+
+use crate::expr::caret;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Word<'a> {
+    // As written, quotes and all.
+    pub text: &'a str,
+    // Byte offsets into the line.
+    pub start: usize,
+    pub end: usize,
+}
+
+// The words of `line`, which has had its comment stripped.
+pub fn words(line: &str) -> Result<Vec<Word<'_>>, String> {
+    let mut words = vec![];
+    let mut at = 0;
+    loop {
+        at += line[at..].len() - line[at..].trim_start().len();
+        if at == line.len() { return Ok(words); }
+        let end = at + word_len(&line[at..]).map_err(|(msg, start, end)| {
+            caret(line, at + start, at + end, msg)
+        })?;
+        words.push(Word { text: &line[at..end], start: at, end });
+        at = end;
+    }
+}
+
+// Where the word at the start of `s` ends. Lenient: an unclosed string or
+// parenthesis runs to the end.
+pub fn word_end(s: &str) -> usize {
+    match word_len(s) {
+        Ok(n) => n,
+        Err(_) => s.len(),
+    }
+}
+
+// The length of the word at the start of `s`, or what's wrong with it and
+// where.
+fn word_len(s: &str) -> Result<usize, (&'static str, usize, usize)> {
+    if s.starts_with('"') {
+        let mut escaped = false;
+        for (i, c) in s.char_indices().skip(1) {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => return Ok(i + 1),
+                _ => {},
+            }
+        }
+        return Err(("unterminated string", 0, 1));
+    }
+    let mut opened = vec![];
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => opened.push(i),
+            ')' => { opened.pop().ok_or(("unmatched \")\"", i, i + 1))?; },
+            '"' if opened.is_empty() => return Ok(i),
+            c if c.is_whitespace() && opened.is_empty() => return Ok(i),
+            _ => {},
+        }
+    }
+    match opened.last() {
+        Some(&i) => Err(("unclosed \"(\"", i, i + 1)),
+        None => Ok(s.len()),
+    }
+}
+
+// How many parentheses are still open at the end of `line`, which has had
+// its comment stripped.
+pub fn open_parens(line: &str) -> usize {
+    let mut at = 0;
+    loop {
+        at += line[at..].len() - line[at..].trim_start().len();
+        let rest = &line[at..];
+        match word_len(rest) {
+            _ if rest.is_empty() => return 0,
+            Ok(n) => at += n.max(1),
+            Err(_) if rest.starts_with('"') => return 0,
+            Err(_) => return paren_depth(rest, 0),
+        }
+    }
+}
+
+// How many parentheses are open after `s`, given `open` before it. Extra
+// closing ones are ignored.
+pub fn paren_depth(s: &str, open: usize) -> usize {
+    s.chars().fold(open, |open, c| match c {
+        '(' => open + 1,
+        ')' => open.saturating_sub(1),
+        _ => open,
+    })
+}
+
+// `line` up to its comment, if it has one, with trailing space removed.
+pub fn strip_comment(line: &str) -> &str {
+    if line.trim_start().starts_with('#') { return ""; }
+    let mut at = 0;
+    loop {
+        at += line[at..].len() - line[at..].trim_start().len();
+        let rest = &line[at..];
+        if rest.is_empty() { break; }
+        if rest.starts_with('#') && !is_hex_color(rest) { return line[..at].trim_end(); }
+        at += word_end(rest).max(1);
+    }
+    line.trim_end()
+}
+
+// Whether `s` starts with a colour like #ff8000 or #ff800080 rather than a
+// comment.
+fn is_hex_color(s: &str) -> bool {
+    let digits = s[1..].chars().take_while(char::is_ascii_hexdigit).count();
+    (digits == 6 || digits == 8)
+        && s[1 + digits..].chars().next().is_none_or(|c| c.is_whitespace() || c == ')')
+}
+
+// Whether the line goes on on the next one, and if so, the line without
+// its trailing \.
+pub fn continued(line: &str) -> Option<&str> {
+    line.strip_suffix('\\')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(line: &str) -> Vec<&str> {
+        words(line).unwrap().iter().map(|w| w.text).collect()
+    }
+
+    #[test]
+    fn splits_on_any_whitespace() {
+        assert_eq!(texts("POINT 1\t2  \t 3\u{a0}4 "), ["POINT", "1", "2", "3", "4"]);
+        let w = words("  a\tbc").unwrap();
+        assert_eq!((w[1].start, w[1].end), (4, 6));
+        assert!(texts(" \t ").is_empty());
+    }
+
+    #[test]
+    fn parentheses_and_strings_are_one_word() {
+        assert_eq!(texts("COLOR rgb(255, 0, 0) (sin(t) * 2)x"),
+            ["COLOR", "rgb(255, 0, 0)", "(sin(t) * 2)x"]);
+        assert_eq!(texts(r#"TEXT "a \"b\" \\" c"#), ["TEXT", r#""a \"b\" \\""#, "c"]);
+        assert_eq!(texts(r#"a"b c""#), ["a", r#""b c""#]);
+    }
+
+    #[test]
+    fn reports_bad_words() {
+        let err = |line: &str| words(line).unwrap_err();
+        assert_eq!(err("POINT (1 2"), "unclosed \"(\"\n    POINT (1 2\n          ^");
+        assert_eq!(err("a b)"), "unmatched \")\"\n    a b)\n       ^");
+        assert_eq!(err(r#"TEXT "abc\""#), "unterminated string\n    TEXT \"abc\\\"\n         ^");
+        // The lenient version runs to the end.
+        assert_eq!(word_end("(1 2"), 4);
+        assert_eq!(word_end("\"ab"), 3);
+    }
+
+    #[test]
+    fn strips_comments() {
+        assert_eq!(strip_comment("# all of it"), "");
+        assert_eq!(strip_comment("POINT 1 2 3 4  # a point"), "POINT 1 2 3 4");
+        assert_eq!(strip_comment("POINT 1 2 3 4#not a comment"), "POINT 1 2 3 4#not a comment");
+        assert_eq!(strip_comment("COLOR #ff8000 #ff800080 #abc"), "COLOR #ff8000 #ff800080");
+        assert_eq!(strip_comment("COLOR mix(#ff8000) # x"), "COLOR mix(#ff8000)");
+        assert_eq!(strip_comment(r##"TEXT "# not" (a # b) # c"##), r##"TEXT "# not" (a # b)"##);
+        assert_eq!(strip_comment("TRIANGLE 1 2 \\ # more"), "TRIANGLE 1 2 \\");
+    }
+
+    #[test]
+    fn continues_lines_ending_in_a_backslash() {
+        assert_eq!(continued("POINT 1 2 \\"), Some("POINT 1 2 "));
+        assert_eq!(continued("POINT 1 2 3 4"), None);
+        assert_eq!(continued(strip_comment("POINT 1 \\  # more")), Some("POINT 1 "));
+    }
+
+    #[test]
+    fn counts_open_parentheses() {
+        assert_eq!(open_parens("POINT (1 (2 3"), 2);
+        assert_eq!(open_parens("POINT (1) 2"), 0);
+        assert_eq!(open_parens(r#"TEXT "(" (a"#), 1);
+        assert_eq!(open_parens(r#"TEXT "(a"#), 0);
+        assert_eq!(paren_depth(" 2) + 3)", 2), 0);
+        assert_eq!(paren_depth(" 2) + (3", 2), 2);
+    }
+}
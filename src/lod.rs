@@ -0,0 +1,115 @@
+use crate::data::*;
+use crate::meshops;
+use crate::transform::Transform;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+// Meshes smaller than this aren't worth simplifying.
+const MIN_TRIANGLES: usize = 512;
+// Screen pixels per triangle we aim for. Counting the hidden back half and
+// the corners of the bounding box, real triangles end up a few times larger.
+const PIXELS_PER_TRIANGLE: f32 = 4.0;
+// How far past a level's switching point the size must go before it
+// changes, so a mesh hovering near one doesn't flicker between levels.
+const HYSTERESIS: f32 = 0.25;
+
+// A mesh and its simplified stand-ins, built once at load time. Clones share
+// the meshes but pick their levels separately.
+#[derive(Debug, Clone)]
+pub struct Lods {
+    // Finest first, each with about half the triangles of the one before.
+    pub levels: Rc<Vec<Mesh>>,
+    // The level chosen last frame.
+    current: Cell<usize>,
+}
+
+impl Lods {
+    pub fn new(mesh: Mesh) -> Lods {
+        let mut levels = vec![mesh];
+        loop {
+            let last = &levels[levels.len() - 1];
+            let n = last.triangles.len();
+            if n < MIN_TRIANGLES * 2 { break; }
+            let next = meshops::decimate(last, n / 2);
+            // Seams and open edges hold some meshes back; stop once a level
+            // no longer saves much.
+            if next.triangles.len() * 4 > n * 3 { break; }
+            levels.push(next);
+        }
+        Lods { levels: Rc::new(levels), current: Cell::new(0) }
+    }
+
+    pub fn full(&self) -> &Mesh {
+        &self.levels[0]
+    }
+
+    // The coarsest level with enough triangles for the mesh's size on screen
+    // under `tr`.
+    pub fn select(&self, tr: Transform) -> &Mesh {
+        if self.levels.len() == 1 { return &self.levels[0]; }
+
+        let Bounds { min, max } = self.levels[0].bounds;
+        let (mut lo, mut hi) = ([f32::MAX; 2], [f32::MIN; 2]);
+        for i in 0..8 {
+            let corner = Point3 {
+                x: if i & 1 == 0 { min.x } else { max.x },
+                y: if i & 2 == 0 { min.y } else { max.y },
+                z: if i & 4 == 0 { min.z } else { max.z },
+            };
+            let p = tr * corner;
+            lo = [lo[0].min(p.x), lo[1].min(p.y)];
+            hi = [hi[0].max(p.x), hi[1].max(p.y)];
+        }
+        let size = (hi[0] - lo[0]).max(hi[1] - lo[1]);
+        let wanted = size * size / PIXELS_PER_TRIANGLE;
+
+        let triangles = |l: usize| self.levels[l].triangles.len() as f32;
+        let mut l = self.current.get();
+        while l + 1 < self.levels.len() && triangles(l + 1) >= wanted * (1.0 + HYSTERESIS) {
+            l += 1;
+        }
+        while l > 0 && triangles(l) < wanted * (1.0 - HYSTERESIS) {
+            l -= 1;
+        }
+        self.current.set(l);
+        &self.levels[l]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives;
+
+    #[test]
+    fn small_meshes_have_one_level() {
+        let lods = Lods::new(primitives::sphere(8, 4));
+        assert_eq!(lods.levels.len(), 1);
+        assert!(std::ptr::eq(lods.select(Transform::IDENTITY), lods.full()));
+    }
+
+    #[test]
+    fn picks_levels_by_size_on_screen() {
+        let lods = Lods::new(primitives::sphere(64, 32));
+        let n = lods.levels.len();
+        assert!(n > 2, "{} levels", n);
+        for pair in lods.levels.windows(2) {
+            assert!(pair[1].triangles.len() < pair[0].triangles.len());
+        }
+        let level = |scale: f32| {
+            let mesh = lods.select(Transform::scale(scale, scale, 1.0));
+            lods.levels.iter().position(|l| std::ptr::eq(l, mesh)).unwrap()
+        };
+        assert_eq!(level(1000.0), 0);
+        assert_eq!(level(1.0), n - 1);
+        // The sphere is 2 across, so it wants scale squared triangles. Asking
+        // for exactly level 1's count is inside the hysteresis either side,
+        // so the level depends on where it came from.
+        let scale = (lods.levels[1].triangles.len() as f32).sqrt();
+        level(1000.0);
+        assert_eq!(level(scale), 0);
+        level(1.0);
+        assert_eq!(level(scale), 1);
+    }
+}
@@ -7,10 +7,29 @@ use std::time::{Instant};
 #[allow(dead_code)]
 mod data;
 mod draw;
+mod easing;
+mod error;
+mod export;
+mod expr;
+mod gltf;
+mod image;
+mod json;
+mod keyframes;
+mod lexer;
+mod lod;
+mod meshops;
+mod mtl;
+mod obj;
+mod paint;
 mod parser;
+mod ply;
+mod primitives;
+mod stl;
+mod text;
 mod transform;
 mod util;
 
+use crate::paint::Paint;
 use crate::transform::Transform;
 
 const SCR_W: u32 = 800;
@@ -18,7 +37,33 @@ const SCR_H: u32 = 600;
 
 const SCENE_PATH: &str = "./scenes/mesh_test.scn";
 
+// Usage: [scene] [--export out.stl|out.obj|out.ply [--at seconds] [--separate]]
+//
+// Exporting writes the file and exits without opening a window. With
+// --separate each mesh gets its own file. --export-stl is the old spelling
+// of --export.
 fn main() {
+    let mut scene_path = SCENE_PATH.to_string();
+    let mut export_path = None;
+    let mut at = 0.0;
+    let mut separate = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--export" | "--export-stl" => export_path = Some(args.next().expect("--export needs a path")),
+            "--at" => at = args.next().and_then(|s| s.parse().ok())
+                .expect("--at needs a time in seconds"),
+            "--separate" => separate = true,
+            _ => scene_path = arg,
+        }
+    }
+
+    if let Some(path) = export_path {
+        let scene = load_or_exit(&scene_path);
+        export::export(&scene, at, &path, separate).unwrap();
+        return;
+    }
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
@@ -35,7 +80,7 @@ fn main() {
         .unwrap();
     let mut screen = draw::Screen::new(SCR_W as usize, SCR_H as usize);
 
-    let mut scene = parser::load_scene(SCENE_PATH).unwrap();
+    let mut scene = load_or_exit(&scene_path);
 
     let mut loop_start = Instant::now();
     let mut event_pump = sdl_context.event_pump().unwrap();
@@ -49,7 +94,11 @@ fn main() {
                     break 'running
                 },
                 Event::MouseButtonDown {..} => {
-                    scene = parser::load_scene(SCENE_PATH).unwrap();
+                    // A broken edit keeps the last good scene on screen.
+                    match parser::load_scene(&scene_path) {
+                        Ok(s) => scene = s,
+                        Err(errors) => eprintln!("{}", errors),
+                    }
                     loop_start = Instant::now();
                     t = 0;
                 },
@@ -79,10 +128,31 @@ fn main() {
     }
 }
 
+fn load_or_exit(path: &str) -> parser::Scene {
+    parser::load_scene(path).unwrap_or_else(|errors| {
+        eprintln!("{}", errors);
+        std::process::exit(1);
+    })
+}
+
 fn draw_scene(
     screen: &mut draw::Screen,
     scene: &parser::Scene,
     t: f32
+) -> Result<(), String> {
+    draw_background(screen, scene, t)?;
+    draw_commands(screen, scene, &scene.commands, t, Transform::IDENTITY, Paint::Solid(data::Color::WHITE, 1.0))
+}
+
+// Draws `commands` inside the transform `outer`: their own transforms apply
+// first, as if they were the whole scene, and `outer` after.
+fn draw_commands(
+    screen: &mut draw::Screen,
+    scene: &parser::Scene,
+    commands: &[parser::Command],
+    t: f32,
+    outer: Transform,
+    mut paint: Paint,
 ) -> Result<(), String> {
     fn ps(p: data::Point3) -> data::PointScreen {
         data::PointScreen { x: p.x as isize, y: p.y as isize }
@@ -90,22 +160,20 @@ fn draw_scene(
     use crate::parser::Command;
     use crate::parser::Eval;
 
-    screen.clear();
-
-    let mut color = data::Color::WHITE;
-    let mut tr = Transform::IDENTITY;
-
-    for cmd in &scene.commands {
+    let mut local = Transform::IDENTITY;
+    let mut saved = vec![];
+    for cmd in commands {
+        let tr = outer * local;
         match cmd {
             Command::Point { p, rad } => {
                 let rad = rad.eval_at(t, scene)?;
                 let p = p.eval_at(t, scene)?;
-                draw::draw_point(screen, ps(tr*p), rad as usize, color)
+                draw::draw_point(screen, ps(tr*p), rad as usize, &paint)
             },
             Command::Line(p1, p2) => {
                 let p1 = p1.eval_at(t, scene)?;
                 let p2 = p2.eval_at(t, scene)?;
-                draw::draw_line(screen, ps(tr*p1), ps(tr*p2), color)
+                draw::draw_line(screen, ps(tr*p1), ps(tr*p2), &paint)
             },
             Command::Triangle(p1, p2, p3) => {
                 let p1 = p1.eval_at(t, scene)?;
@@ -114,43 +182,46 @@ fn draw_scene(
                 draw::draw_triangle(
                     screen,
                     ps(tr*p1), ps(tr*p2), ps(tr*p3),
-                    color);
+                    &paint);
             },
-            Command::Mesh{ points, triangles } => {
-                let mut pts: Vec<data::PointScreen> = Vec::with_capacity(points.len());
-                for pt in points {
-                    pts.push(ps(tr*(*pt)));
-                }
-                for i in 0..triangles.len() / 3 {
-                    draw::draw_triangle(
-                        screen,
-                        pts[triangles[i*3 + 0]],
-                        pts[triangles[i*3 + 1]],
-                        pts[triangles[i*3 + 2]],
-                        color);
+            Command::Mesh(lods) => draw_mesh(screen, lods.select(tr), tr, &paint),
+            Command::Model(model) => {
+                for (local, mesh) in model.instances() {
+                    draw_mesh(screen, mesh, tr * local, &paint);
                 }
             },
-
-            Command::Scale(x, y, z) => {
-                let x = x.eval_at(t, scene)?;
-                let y = y.eval_at(t, scene)?;
-                let z = z.eval_at(t, scene)?;
-                tr = Transform::scale(x, y, z) * tr;
+            Command::Sprite(sprite) => draw_sprite(screen, sprite, tr, t, scene)?,
+            Command::Text { p, text, size, align, projected, font } => {
+                let p = p.eval_at(t, scene)?;
+                let p = if *projected { tr*p } else { p };
+                let s = text::eval_template(text, t, scene)?;
+                let size = size.eval_at(t, scene)?;
+                text::draw_text(screen, font, &s, ps(p), size, *align, &paint);
             },
-            Command::Translate(x, y, z) => {
-                let x = x.eval_at(t, scene)?;
-                let y = y.eval_at(t, scene)?;
-                let z = z.eval_at(t, scene)?;
-                tr = Transform::translate(x, y, z) * tr;
+
+            Command::Scale(..) | Command::Translate(..) | Command::Rotate { .. } |
+            Command::Identity => local = cmd.transform(local, t, scene)?,
+            Command::Push => saved.push((local, paint.clone())),
+            Command::Pop => (local, paint) = saved.pop()
+                .expect("the loader pairs every POP with a PUSH before it"),
+
+            Command::Color(c) => {
+                let (c, alpha) = c.eval_at(t, scene)?;
+                paint = Paint::Solid(c, alpha);
             },
-            Command::Rotate { theta, v } => {
-                let theta = theta.eval_at(t, scene)?;
-                let v = v.eval_at(t, scene)?;
-                tr = Transform::rotate(theta, v) * tr;
+            Command::Gradient { shape, spread, stops } => {
+                paint = Paint::Gradient(paint::Gradient {
+                    shape: eval_shape(shape, tr, t, scene)?,
+                    spread: *spread,
+                    stops: stops.iter()
+                        .map(|s| eval_stop(s.offset, &s.color, t, scene))
+                        .collect::<Result<_, _>>()?,
+                });
             },
-            Command::Identity => tr = Transform::IDENTITY,
 
-            Command::Color(c) => color = *c,
+            Command::Group { name, scope, commands } => scene.in_scope(*scope, || {
+                draw_commands(screen, scene, commands, t, tr, paint.clone())
+            }).map_err(|e| format!("{}: {}", name, e))?,
 
             #[allow(unreachable_patterns)]
             _ => return Err(format!("command not implemented: {:?}", cmd))
@@ -158,4 +229,175 @@ fn draw_scene(
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+fn draw_background(
+    screen: &mut draw::Screen,
+    scene: &parser::Scene,
+    t: f32
+) -> Result<(), String> {
+    use crate::parser::{Background, Eval};
+
+    match &scene.background {
+        Background::None => {},
+        Background::Solid(c) => {
+            let (c, alpha) = c.eval_at(t, scene)?;
+            screen.fill(&Paint::Solid(c, alpha));
+        },
+        Background::Gradient { vertical, from, to } => {
+            let end = if *vertical {
+                data::Point2 { x: 0.0, y: screen.h as f32 }
+            } else {
+                data::Point2 { x: screen.w as f32, y: 0.0 }
+            };
+            screen.fill(&Paint::Gradient(paint::Gradient {
+                shape: paint::Shape::Linear { from: data::Point2 { x: 0.0, y: 0.0 }, to: end },
+                spread: paint::Spread::Pad,
+                stops: vec![eval_stop(0.0, from, t, scene)?, eval_stop(1.0, to, t, scene)?],
+            }));
+        },
+        Background::Image { image, fit } => screen.fill_image(image, *fit),
+    }
+    Ok(())
+}
+
+// Each group with a material draws in its diffuse colour or texture; other
+// triangles use the vertex colours if there are any, or else the current
+// paint. A mesh without triangles is drawn as a point cloud.
+fn draw_mesh(screen: &mut draw::Screen, mesh: &data::Mesh, tr: Transform, paint: &Paint) {
+    let pts: Vec<data::PointScreen> = mesh.points.iter()
+        .map(|&p| { let p = tr*p; data::PointScreen { x: p.x as isize, y: p.y as isize } })
+        .collect();
+    if mesh.triangles.is_empty() {
+        for (i, &p) in pts.iter().enumerate() {
+            // draw_point clamps to the screen, so skip points that are off it.
+            if p.x < 0 || p.y < 0 || p.x >= screen.w as isize || p.y >= screen.h as isize {
+                continue;
+            }
+            match mesh.colors.as_ref().map(|c| c[i]) {
+                Some(c) => draw::draw_point(screen, p, 0, &Paint::Solid(c, 1.0)),
+                None => draw::draw_point(screen, p, 0, paint),
+            }
+        }
+        return;
+    }
+    let draw_range = |screen: &mut draw::Screen, from: usize, to: usize, material: Option<&data::Material>| {
+        for i in from..to {
+            let [a, b, c] = mesh.triangle(i);
+            match (material, &mesh.colors, &mesh.uvs) {
+                (None, Some(colors), _) => draw::draw_triangle_shaded(
+                    screen, [pts[a], pts[b], pts[c]], [colors[a], colors[b], colors[c]]),
+                (None, None, _) => draw::draw_triangle(screen, pts[a], pts[b], pts[c], paint),
+                (Some(m), _, Some(uvs)) if m.diffuse_map.is_some() =>
+                    draw::draw_triangle_textured(
+                        screen, [pts[a], pts[b], pts[c]], [uvs[a], uvs[b], uvs[c]],
+                        m.diffuse_map.as_deref(), m.diffuse_color(), m.opacity),
+                (Some(m), _, _) if m.opacity >= 1.0 =>
+                    draw::draw_triangle(screen, pts[a], pts[b], pts[c],
+                        &Paint::Solid(m.diffuse_color(), 1.0)),
+                (Some(m), _, _) => {
+                    let uv = data::Point2 { x: 0.0, y: 0.0 };
+                    draw::draw_triangle_textured(
+                        screen, [pts[a], pts[b], pts[c]], [uv; 3], None, m.diffuse_color(), m.opacity);
+                },
+            }
+        }
+    };
+
+    let mut done = 0;
+    for g in &mesh.groups {
+        draw_range(screen, done, g.start, None);
+        let material = g.material.as_ref()
+            .and_then(|name| mesh.materials.iter().find(|m| &m.name == name));
+        draw_range(screen, g.start, g.start + g.count, material);
+        done = g.start + g.count;
+    }
+    draw_range(screen, done, mesh.triangles.len(), None);
+}
+
+fn draw_sprite(
+    screen: &mut draw::Screen,
+    sprite: &parser::Sprite,
+    tr: Transform,
+    t: f32,
+    scene: &parser::Scene
+) -> Result<(), String> {
+    use crate::parser::Eval;
+    fn p2(p: data::Point3) -> data::Point2 {
+        data::Point2 { x: p.x, y: p.y }
+    }
+
+    let image = &sprite.image;
+    let (cols, rows) = sprite.sheet;
+    let frame = sprite.frame.eval_at(t, scene)?.floor() as isize;
+    let frame = frame.rem_euclid((cols * rows) as isize) as usize;
+    let (w, h) = (image.w / cols, image.h / rows);
+    let rect = image::Rect { x: frame % cols * w, y: frame / cols * h, w, h };
+
+    let p = sprite.p.eval_at(t, scene)?;
+    let scale = sprite.scale.eval_at(t, scene)?;
+    let (sin, cos) = sprite.rotate.eval_at(t, scene)?.sin_cos();
+    let u = data::Point3 { x: cos, y: sin, z: 0.0 } * (w as f32 * scale);
+    let v = data::Point3 { x: -sin, y: cos, z: 0.0 } * (h as f32 * scale);
+
+    let c = tr*p;
+    let quad = if sprite.billboard {
+        draw::Quad { center: p2(c), u: p2(u), v: p2(v) }
+    } else {
+        draw::Quad { center: p2(c), u: p2(tr*(p + u) - c), v: p2(tr*(p + v) - c) }
+    };
+    let alpha = sprite.alpha.eval_at(t, scene)?;
+    draw::draw_image(screen, image, rect, quad, alpha, sprite.filter);
+    Ok(())
+}
+
+fn eval_stop(
+    offset: f32,
+    color: &parser::ValColor,
+    t: f32,
+    scene: &parser::Scene
+) -> Result<paint::Stop, String> {
+    use crate::parser::Eval;
+    let (color, alpha) = color.eval_at(t, scene)?;
+    Ok(paint::Stop { offset, color, alpha })
+}
+
+// Evaluates a gradient's geometry and takes it to screen space.
+fn eval_shape(
+    shape: &parser::ValShape,
+    tr: Transform,
+    t: f32,
+    scene: &parser::Scene
+) -> Result<paint::Shape, String> {
+    use crate::parser::{Eval, ValShape};
+    fn p2(p: data::Point3) -> data::Point2 {
+        data::Point2 { x: p.x, y: p.y }
+    }
+
+    Ok(match shape {
+        ValShape::Linear { from, to } => paint::Shape::Linear {
+            from: p2(tr*from.eval_at(t, scene)?),
+            to:   p2(tr*to.eval_at(t, scene)?),
+        },
+        // The radius and angle are measured along the transformed x axis.
+        ValShape::Radial { center, radius } => {
+            let c = center.eval_at(t, scene)?;
+            let edge = data::Point3 { x: c.x + radius.eval_at(t, scene)?, ..c };
+            let (c, edge) = (tr*c, tr*edge);
+            paint::Shape::Radial {
+                center: p2(c),
+                radius: ((edge.x - c.x).powi(2) + (edge.y - c.y).powi(2)).sqrt(),
+            }
+        },
+        ValShape::Conic { center, angle } => {
+            let c = center.eval_at(t, scene)?;
+            let a = angle.eval_at(t, scene)?;
+            let dir = data::Point3 { x: c.x + a.cos(), y: c.y + a.sin(), ..c };
+            let (c, dir) = (tr*c, tr*dir);
+            paint::Shape::Conic {
+                center: p2(c),
+                angle: (dir.y - c.y).atan2(dir.x - c.x),
+            }
+        },
+    })
+}
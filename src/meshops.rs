@@ -0,0 +1,642 @@
+use crate::data::*;
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+// Clean-up steps that can follow a mesh command, run in the order given.
+#[derive(Debug, Copy, Clone)]
+pub enum MeshOp {
+    // Smooth normals blend the faces around a point whose normals are within
+    // `crease` degrees of each other; flat normals give each face its own.
+    Normals { smooth: bool, crease: f32 },
+    // Merges points closer than the given distance.
+    Weld(f32),
+    // Levels of Loop subdivision.
+    Subdivide(usize),
+}
+
+pub fn apply(mesh: &mut Mesh, ops: &[MeshOp]) -> Result<(), String> {
+    for op in ops {
+        match *op {
+            MeshOp::Normals { smooth: true, crease } => smooth_normals(mesh, crease),
+            MeshOp::Normals { smooth: false, .. } => flat_normals(mesh),
+            MeshOp::Weld(eps) => weld(mesh, eps),
+            MeshOp::Subdivide(levels) => for _ in 0..levels { subdivide(mesh); },
+        }
+    }
+    mesh.validate()
+}
+
+// Attribute streams for a new list of points, each copied from `sources`.
+fn pick<T: Copy>(stream: &Option<Vec<T>>, sources: &[usize]) -> Option<Vec<T>> {
+    stream.as_ref().map(|s| sources.iter().map(|&i| s[i]).collect())
+}
+
+// Replaces the points with copies of `sources`, keeping their attributes.
+fn rebuild(mesh: &mut Mesh, sources: &[usize]) {
+    mesh.points = sources.iter().map(|&i| mesh.points[i]).collect();
+    mesh.normals = pick(&mesh.normals, sources);
+    mesh.uvs = pick(&mesh.uvs, sources);
+    mesh.colors = pick(&mesh.colors, sources);
+}
+
+// Drops the triangles `keep` rejects, shrinking groups to match.
+fn retain_triangles<F>(mesh: &mut Mesh, keep: F)
+where F: Fn(&[u32; 3]) -> bool {
+    // Where each old triangle lands in the new list.
+    let mut at = Vec::with_capacity(mesh.triangles.len() + 1);
+    let mut kept = Vec::with_capacity(mesh.triangles.len());
+    for t in &mesh.triangles {
+        at.push(kept.len());
+        if keep(t) { kept.push(*t); }
+    }
+    at.push(kept.len());
+    for g in mesh.groups.iter_mut() {
+        let (start, end) = (at[g.start], at[g.start + g.count]);
+        g.start = start;
+        g.count = end - start;
+    }
+    mesh.triangles = kept;
+}
+
+// Exact position, with -0.0 folded into 0.0.
+fn key(p: Point3) -> [u32; 3] {
+    [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()]
+}
+
+// Twice the area, in the direction of the face's normal.
+fn face_normal(points: &[Point3], t: [u32; 3]) -> Point3 {
+    let [a, b, c] = t.map(|i| points[i as usize]);
+    (b - a).cross(c - a)
+}
+
+pub fn weld(mesh: &mut Mesh, eps: f32) {
+    // Points are bucketed in cubes of side `eps`, so a match can only be in
+    // the same cube or a neighbouring one.
+    let cell = |p: Point3| -> [i64; 3] {
+        if eps > 0.0 {
+            [(p.x / eps).floor() as i64, (p.y / eps).floor() as i64, (p.z / eps).floor() as i64]
+        } else {
+            let k = key(p);
+            [k[0] as i64, k[1] as i64, k[2] as i64]
+        }
+    };
+    let mut cells: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+    let mut sources: Vec<usize> = vec![];
+    let mut remap = Vec::with_capacity(mesh.points.len());
+    for (i, &p) in mesh.points.iter().enumerate() {
+        let c = cell(p);
+        let range = if eps > 0.0 { -1..2 } else { 0..1 };
+        let mut found = None;
+        'search: for dx in range.clone() {
+            for dy in range.clone() {
+                for dz in range.clone() {
+                    let near = cells.get(&[c[0] + dx, c[1] + dy, c[2] + dz]);
+                    for &r in near.into_iter().flatten() {
+                        let q = mesh.points[sources[r as usize]];
+                        if (q - p).magnitude() <= eps {
+                            found = Some(r);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+        let r = found.unwrap_or_else(|| {
+            sources.push(i);
+            let r = sources.len() as u32 - 1;
+            cells.entry(c).or_default().push(r);
+            r
+        });
+        remap.push(r);
+    }
+
+    for t in mesh.triangles.iter_mut() {
+        *t = t.map(|i| remap[i as usize]);
+    }
+    retain_triangles(mesh, |t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0]);
+    rebuild(mesh, &sources);
+}
+
+// Gives each corner its own normal, then splits points whose corners ended
+// up with different ones.
+fn set_corner_normals(mesh: &mut Mesh, corners: &[Point3]) {
+    let mut index: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+    let mut sources = vec![];
+    let mut normals = vec![];
+    for (i, t) in mesh.triangles.iter_mut().enumerate() {
+        for k in 0..3 {
+            let (v, n) = (t[k], corners[i * 3 + k]);
+            t[k] = *index.entry((v, key(n))).or_insert_with(|| {
+                sources.push(v as usize);
+                normals.push(n);
+                sources.len() as u32 - 1
+            });
+        }
+    }
+    rebuild(mesh, &sources);
+    mesh.normals = Some(normals);
+}
+
+pub fn flat_normals(mesh: &mut Mesh) {
+    let corners: Vec<Point3> = mesh.triangles.iter()
+        .flat_map(|&t| [face_normal(&mesh.points, t).normalized(); 3])
+        .collect();
+    set_corner_normals(mesh, &corners);
+}
+
+// Faces meeting at a point are found by position, so seams and duplicated
+// points still smooth across.
+pub fn smooth_normals(mesh: &mut Mesh, crease: f32) {
+    let faces: Vec<Point3> = mesh.triangles.iter().map(|&t| face_normal(&mesh.points, t)).collect();
+    let mut around: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    for (f, t) in mesh.triangles.iter().enumerate() {
+        for &v in t {
+            let list = around.entry(key(mesh.points[v as usize])).or_default();
+            if !list.contains(&f) { list.push(f); }
+        }
+    }
+
+    let cos = crease.to_radians().cos();
+    let mut corners = Vec::with_capacity(mesh.triangles.len() * 3);
+    for (f, t) in mesh.triangles.iter().enumerate() {
+        let own = faces[f].normalized();
+        for &v in t {
+            // Larger faces count for more, since `faces` isn't normalised.
+            let n = around[&key(mesh.points[v as usize])].iter()
+                .filter(|&&g| g == f || own.dot(faces[g].normalized()) >= cos)
+                .fold(Point3 { x: 0.0, y: 0.0, z: 0.0 }, |sum, &g| sum + faces[g]);
+            corners.push(n.normalized());
+        }
+    }
+    set_corner_normals(mesh, &corners);
+}
+
+struct Edge {
+    // The third corner of each triangle on the edge.
+    opposite: Vec<u32>,
+    // The point added at its middle.
+    mid: u32,
+}
+
+// One level of Loop subdivision. Edges with one triangle, or more than two,
+// are boundaries: their points stay where they are and they are split at
+// their midpoints, so open edges and seams keep their exact shape.
+pub fn subdivide(mesh: &mut Mesh) {
+    let n = mesh.points.len();
+    let mut edges: HashMap<(u32, u32), Edge> = HashMap::new();
+    let mut sources: Vec<(usize, usize)> = (0..n).map(|i| (i, i)).collect();
+    for t in &mesh.triangles {
+        for k in 0..3 {
+            let (a, b, c) = (t[k], t[(k + 1) % 3], t[(k + 2) % 3]);
+            let e = edges.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                sources.push((a as usize, b as usize));
+                Edge { opposite: vec![], mid: sources.len() as u32 - 1 }
+            });
+            e.opposite.push(c);
+        }
+    }
+
+    let mut neighbours: Vec<Vec<u32>> = vec![vec![]; n];
+    let mut on_boundary = vec![false; n];
+    for (&(a, b), e) in &edges {
+        neighbours[a as usize].push(b);
+        neighbours[b as usize].push(a);
+        if e.opposite.len() != 2 {
+            on_boundary[a as usize] = true;
+            on_boundary[b as usize] = true;
+        }
+    }
+    // HashMap order varies from run to run; sorting keeps the sums below
+    // exactly repeatable.
+    for list in neighbours.iter_mut() { list.sort_unstable(); }
+
+    let p = |i: u32| mesh.points[i as usize];
+    let mut points = Vec::with_capacity(sources.len());
+    for v in 0..n {
+        let here = mesh.points[v];
+        let moved = match neighbours[v].len() {
+            _ if on_boundary[v] => here,
+            0 => here,
+            valence => {
+                let beta = if valence == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * valence as f32) };
+                let sum = neighbours[v].iter()
+                    .fold(Point3 { x: 0.0, y: 0.0, z: 0.0 }, |s, &u| s + p(u));
+                here * (1.0 - valence as f32 * beta) + sum * beta
+            },
+        };
+        points.push(moved);
+    }
+    let mut mids: Vec<(u32, Point3)> = edges.iter()
+        .map(|(&(a, b), e)| {
+            let mid = match e.opposite.as_slice() {
+                [c, d] => (p(a) + p(b)) * 0.375 + (p(*c) + p(*d)) * 0.125,
+                _ => (p(a) + p(b)) * 0.5,
+            };
+            (e.mid, mid)
+        })
+        .collect();
+    mids.sort_by_key(|&(i, _)| i);
+    points.extend(mids.into_iter().map(|(_, q)| q));
+
+    // Other attributes are interpolated along the edge.
+    fn lerp<T: Copy, F>(stream: &Option<Vec<T>>, sources: &[(usize, usize)], mix: F) -> Option<Vec<T>>
+    where F: Fn(T, T) -> T {
+        stream.as_ref().map(|s| sources.iter()
+            .map(|&(a, b)| if a == b { s[a] } else { mix(s[a], s[b]) })
+            .collect())
+    }
+    mesh.normals = lerp(&mesh.normals, &sources, |a, b| (a + b).normalized());
+    mesh.uvs = lerp(&mesh.uvs, &sources,
+        |a, b| Point2 { x: (a.x + b.x) / 2.0, y: (a.y + b.y) / 2.0 });
+    mesh.colors = lerp(&mesh.colors, &sources, |a, b| Color {
+        r: ((a.r as u16 + b.r as u16) / 2) as u8,
+        g: ((a.g as u16 + b.g as u16) / 2) as u8,
+        b: ((a.b as u16 + b.b as u16) / 2) as u8,
+    });
+    mesh.points = points;
+
+    let mid = |a: u32, b: u32| edges[&(a.min(b), a.max(b))].mid;
+    let mut triangles = Vec::with_capacity(mesh.triangles.len() * 4);
+    for &[a, b, c] in &mesh.triangles {
+        let (ab, bc, ca) = (mid(a, b), mid(b, c), mid(c, a));
+        triangles.extend_from_slice(&[[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]);
+    }
+    mesh.triangles = triangles;
+    for g in mesh.groups.iter_mut() {
+        g.start *= 4;
+        g.count *= 4;
+    }
+}
+
+// Sum of squared distances to a set of planes, as the upper triangle of a
+// symmetric 4x4 matrix.
+#[derive(Copy, Clone, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn plane(n: Point3, through: Point3, weight: f64) -> Quadric {
+        let [a, b, c] = [n.x as f64, n.y as f64, n.z as f64];
+        let d = -(n.dot(through) as f64);
+        Quadric([a*a, a*b, a*c, a*d, b*b, b*c, b*d, c*c, c*d, d*d].map(|x| x * weight))
+    }
+
+    fn add(self, o: Quadric) -> Quadric {
+        let mut q = self.0;
+        for (x, y) in q.iter_mut().zip(o.0) { *x += y; }
+        Quadric(q)
+    }
+
+    fn error(&self, p: Point3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+        q[0]*x*x + q[4]*y*y + q[7]*z*z + q[9]
+            + 2.0 * (q[1]*x*y + q[2]*x*z + q[5]*y*z + q[3]*x + q[6]*y + q[8]*z)
+    }
+
+    // The point of least error, unless the planes leave it a line or more.
+    fn minimum(&self) -> Option<Point3> {
+        let q = &self.0;
+        let det3 = |m: [f64; 9]| m[0] * (m[4]*m[8] - m[5]*m[7])
+            - m[1] * (m[3]*m[8] - m[5]*m[6])
+            + m[2] * (m[3]*m[7] - m[4]*m[6]);
+        let a = [q[0], q[1], q[2], q[1], q[4], q[5], q[2], q[5], q[7]];
+        let b = [-q[3], -q[6], -q[8]];
+        let det = det3(a);
+        let scale = q[0] + q[4] + q[7];
+        if det.abs() <= 1e-9 * scale * scale * scale { return None; }
+        // Cramer's rule, one column at a time.
+        let solve = |col: usize| {
+            let mut m = a;
+            for row in 0..3 { m[row * 3 + col] = b[row]; }
+            (det3(m) / det) as f32
+        };
+        Some(Point3 { x: solve(0), y: solve(1), z: solve(2) })
+    }
+}
+
+// An edge collapse waiting in the queue. It goes stale when either end has
+// changed since it was costed.
+struct Collapse {
+    cost: f64,
+    edge: (u32, u32),
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, o: &Self) -> bool { self.cost == o.cost }
+}
+impl Eq for Collapse {}
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, o: &Self) -> Option<Ordering> { Some(self.cmp(o)) }
+}
+impl Ord for Collapse {
+    // Reversed, so the heap hands out the cheapest first.
+    fn cmp(&self, o: &Self) -> Ordering { o.cost.total_cmp(&self.cost) }
+}
+
+// Simplifies towards `target` triangles by collapsing the edges whose merged
+// point strays least from the planes of the original faces. Open edges are
+// held in place by extra planes standing on them. Points that share their
+// position with another vertex, where normals or UVs split, never move, so
+// seams can't crack open. Collapses that would fold a face over or pinch
+// the surface are skipped.
+pub fn decimate(mesh: &Mesh, target: usize) -> Mesh {
+    let n = mesh.points.len();
+    let mut out = mesh.clone();
+    let mut quadrics = vec![Quadric::default(); n];
+    let mut faces_of: Vec<Vec<u32>> = vec![vec![]; n];
+    let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+    for (f, &t) in mesh.triangles.iter().enumerate() {
+        let normal = face_normal(&mesh.points, t);
+        let area = normal.magnitude() as f64 / 2.0;
+        let q = Quadric::plane(normal.normalized(), mesh.points[t[0] as usize], area);
+        for k in 0..3 {
+            let (a, b) = (t[k], t[(k + 1) % 3]);
+            quadrics[a as usize] = quadrics[a as usize].add(q);
+            faces_of[a as usize].push(f as u32);
+            *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+    for (f, &t) in mesh.triangles.iter().enumerate() {
+        for k in 0..3 {
+            let (a, b) = (t[k], t[(k + 1) % 3]);
+            if edges[&(a.min(b), a.max(b))] != 1 { continue; }
+            // A steep plane through the edge, at right angles to the face.
+            let (pa, pb) = (mesh.points[a as usize], mesh.points[b as usize]);
+            let side = (pb - pa).cross(face_normal(&mesh.points, mesh.triangles[f])).normalized();
+            let length = (pb - pa).magnitude() as f64;
+            let q = Quadric::plane(side, pa, 1000.0 * length * length);
+            quadrics[a as usize] = quadrics[a as usize].add(q);
+            quadrics[b as usize] = quadrics[b as usize].add(q);
+        }
+    }
+    let mut shared: HashMap<[u32; 3], usize> = HashMap::new();
+    for &p in &mesh.points { *shared.entry(key(p)).or_default() += 1; }
+    let locked: Vec<bool> = mesh.points.iter().map(|&p| shared[&key(p)] > 1).collect();
+
+    // Where `b` merges into `a`, and what that costs. A locked end keeps its
+    // position; otherwise the best point is used, or failing that the best
+    // of the two ends and their middle.
+    let cost = |points: &[Point3], quadrics: &[Quadric], a: u32, b: u32| -> Option<(f64, Point3)> {
+        let (a, b) = (a as usize, b as usize);
+        let q = quadrics[a].add(quadrics[b]);
+        let p = match (locked[a], locked[b]) {
+            (true, true) => return None,
+            (true, false) => points[a],
+            (false, true) => points[b],
+            (false, false) => q.minimum().unwrap_or_else(|| {
+                let mid = (points[a] + points[b]) * 0.5;
+                [points[a], points[b], mid].iter().copied()
+                    .min_by(|x, y| q.error(*x).total_cmp(&q.error(*y)))
+                    .unwrap()
+            }),
+        };
+        Some((q.error(p), p))
+    };
+
+    let points = &mut out.points;
+    let mut versions = vec![0u32; n];
+    let mut heap = BinaryHeap::new();
+    for &(a, b) in edges.keys() {
+        if let Some((c, _)) = cost(points, &quadrics, a, b) {
+            heap.push(Collapse { cost: c, edge: (a, b), versions: (0, 0) });
+        }
+    }
+
+    let triangles = &mut out.triangles;
+    let mut alive = mesh.triangles.len();
+    let live = |triangles: &[[u32; 3]], f: u32| {
+        let t = triangles[f as usize];
+        t[0] != t[1]
+    };
+    while alive > target {
+        let (a, b) = match heap.pop() {
+            Some(c) if c.versions == (versions[c.edge.0 as usize], versions[c.edge.1 as usize]) => c.edge,
+            Some(_) => continue,
+            None => break,
+        };
+        let (ai, bi) = (a as usize, b as usize);
+        let p = match cost(points, &quadrics, a, b) {
+            Some((_, p)) => p,
+            None => continue,
+        };
+
+        // The faces on the edge, and the points around each end.
+        let around = |faces_of: &[Vec<u32>], triangles: &[[u32; 3]], v: usize| -> Vec<u32> {
+            let mut list: Vec<u32> = faces_of[v].iter()
+                .filter(|&&f| live(triangles, f))
+                .flat_map(|&f| triangles[f as usize])
+                .filter(|&u| u as usize != v)
+                .collect();
+            list.sort_unstable();
+            list.dedup();
+            list
+        };
+        let on_edge = faces_of[ai].iter()
+            .filter(|&&f| live(triangles, f) && triangles[f as usize].contains(&b))
+            .count();
+        let (near_a, near_b) = (around(&faces_of, triangles, ai), around(&faces_of, triangles, bi));
+        if on_edge == 0 { continue; }
+        let common = near_a.iter().filter(|u| near_b.contains(u)).count();
+        if common != on_edge { continue; }
+
+        // No remaining face may turn over.
+        let flips = [(ai, b), (bi, a)].iter().any(|&(v, other)| {
+            faces_of[v].iter()
+                .filter(|&&f| live(triangles, f) && !triangles[f as usize].contains(&other))
+                .any(|&f| {
+                    let t = triangles[f as usize];
+                    let before = face_normal(points, t);
+                    let moved = t.map(|u| if u as usize == v { p } else { points[u as usize] });
+                    let after = (moved[1] - moved[0]).cross(moved[2] - moved[0]);
+                    before.dot(after) <= 0.0
+                })
+        });
+        if flips { continue; }
+
+        // Attributes follow the new point's place along the edge.
+        let (pa, pb) = (points[ai], points[bi]);
+        let length = (pb - pa).dot(pb - pa);
+        let s = if length > 0.0 { ((p - pa).dot(pb - pa) / length).clamp(0.0, 1.0) } else { 0.0 };
+        if let Some(normals) = out.normals.as_mut() {
+            normals[ai] = (normals[ai] * (1.0 - s) + normals[bi] * s).normalized();
+        }
+        if let Some(uvs) = out.uvs.as_mut() {
+            let (u, w) = (uvs[ai], uvs[bi]);
+            uvs[ai] = Point2 { x: u.x + (w.x - u.x) * s, y: u.y + (w.y - u.y) * s };
+        }
+        if let Some(colors) = out.colors.as_mut() {
+            let (c, d) = (colors[ai], colors[bi]);
+            let mix = |x: u8, y: u8| (x as f32 + (y as f32 - x as f32) * s).round() as u8;
+            colors[ai] = Color { r: mix(c.r, d.r), g: mix(c.g, d.g), b: mix(c.b, d.b) };
+        }
+        points[ai] = p;
+        quadrics[ai] = quadrics[ai].add(quadrics[bi]);
+
+        for f in std::mem::take(&mut faces_of[bi]) {
+            if !live(triangles, f) { continue; }
+            let t = &mut triangles[f as usize];
+            if t.contains(&a) {
+                // Dead faces are left degenerate and swept up at the end.
+                *t = [a; 3];
+                alive -= 1;
+            } else {
+                for u in t.iter_mut() { if *u == b { *u = a; } }
+                faces_of[ai].push(f);
+            }
+        }
+        versions[ai] += 1;
+        versions[bi] += 1;
+        for u in around(&faces_of, triangles, ai) {
+            if let Some((c, _)) = cost(points, &quadrics, a, u) {
+                let edge = (a, u);
+                heap.push(Collapse { cost: c, edge, versions: (versions[ai], versions[u as usize]) });
+            }
+        }
+    }
+
+    retain_triangles(&mut out, |t| t[0] != t[1]);
+    // Drop the points no face uses any more.
+    let mut remap = vec![u32::MAX; n];
+    let mut sources = vec![];
+    for t in out.triangles.iter_mut() {
+        for v in t.iter_mut() {
+            if remap[*v as usize] == u32::MAX {
+                remap[*v as usize] = sources.len() as u32;
+                sources.push(*v as usize);
+            }
+            *v = remap[*v as usize];
+        }
+    }
+    rebuild(&mut out, &sources);
+    out.bounds = Bounds::of(&out.points);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p3(x: f32, y: f32, z: f32) -> Point3 {
+        Point3 { x, y, z }
+    }
+
+    fn close(a: Point3, b: Point3) -> bool {
+        (a - b).magnitude() < 1e-5
+    }
+
+    // Two triangles folded along the y axis at a right angle, each with its
+    // own copies of the shared corners.
+    fn fold() -> Mesh {
+        let mut mesh = Mesh::new();
+        mesh.points = vec![
+            p3(0.0, 0.0, 0.0), p3(0.0, 1.0, 0.0), p3(-1.0, 0.0, 0.0),
+            p3(0.0, 0.0, 0.0), p3(0.0, 0.0, -1.0), p3(0.0, 1.0, 0.0),
+        ];
+        mesh.triangles = vec![[0, 1, 2], [3, 4, 5]];
+        mesh
+    }
+
+    // A closed tetrahedron, wound outwards.
+    fn tetrahedron() -> Mesh {
+        let mut mesh = Mesh::new();
+        mesh.points = vec![p3(1.0, 1.0, 1.0), p3(1.0, -1.0, -1.0), p3(-1.0, 1.0, -1.0), p3(-1.0, -1.0, 1.0)];
+        mesh.triangles = vec![[0, 1, 2], [0, 3, 1], [0, 2, 3], [1, 3, 2]];
+        mesh
+    }
+
+    #[test]
+    fn weld_merges_close_points() {
+        let mut mesh = fold();
+        mesh.points[3].x = 0.001;
+        weld(&mut mesh, 0.0);
+        assert_eq!(mesh.points.len(), 5);
+        weld(&mut mesh, 0.01);
+        assert_eq!(mesh.points.len(), 4);
+        assert_eq!(mesh.triangles, [[0, 1, 2], [0, 3, 1]]);
+        // Closing up a triangle drops it.
+        weld(&mut mesh, 1.5);
+        assert!(mesh.triangles.is_empty());
+    }
+
+    #[test]
+    fn flat_normals_follow_the_faces() {
+        let mut mesh = fold();
+        weld(&mut mesh, 0.0);
+        flat_normals(&mut mesh);
+        // The two shared corners split again, one copy for each face.
+        assert_eq!(mesh.points.len(), 6);
+        let normals = mesh.normals.unwrap();
+        for (t, n) in mesh.triangles.iter().zip([p3(0.0, 0.0, 1.0), p3(1.0, 0.0, 0.0)]) {
+            assert!(t.iter().all(|&v| close(normals[v as usize], n)));
+        }
+    }
+
+    #[test]
+    fn smooth_normals_keep_creases() {
+        let mut mesh = fold();
+        smooth_normals(&mut mesh, 100.0);
+        let normals = mesh.normals.as_ref().unwrap();
+        let half = p3(1.0, 0.0, 1.0).normalized();
+        assert!(close(normals[0], half) && close(normals[3], half));
+        assert!(close(normals[2], p3(0.0, 0.0, 1.0)));
+
+        // Below the angle between the faces, the fold stays sharp.
+        let mut mesh = fold();
+        smooth_normals(&mut mesh, 80.0);
+        assert!(close(mesh.normals.as_ref().unwrap()[0], p3(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn subdivide_splits_and_smooths() {
+        let mut mesh = tetrahedron();
+        mesh.groups.push(MeshGroup { name: "g".to_string(), material: None, start: 1, count: 2 });
+        subdivide(&mut mesh);
+        assert_eq!((mesh.points.len(), mesh.triangles.len()), (10, 16));
+        assert_eq!((mesh.groups[0].start, mesh.groups[0].count), (4, 8));
+        // Valence 3: 1 - 3 * 3/16 of the point, plus 3/16 of each neighbour,
+        // which sum to minus the point.
+        assert!(close(mesh.points[0], p3(0.25, 0.25, 0.25)));
+        // Edge points: 3/8 of each end and 1/8 of each opposite corner.
+        assert!(mesh.points[4..].iter().any(|&q| close(q, p3(0.5, 0.0, 0.0))));
+        mesh.validate().unwrap();
+    }
+
+    #[test]
+    fn subdivide_keeps_open_edges() {
+        let mut mesh = Mesh::new();
+        mesh.points = vec![p3(0.0, 0.0, 0.0), p3(2.0, 0.0, 0.0), p3(0.0, 2.0, 0.0)];
+        mesh.triangles = vec![[0, 1, 2]];
+        subdivide(&mut mesh);
+        assert!(close(mesh.points[1], p3(2.0, 0.0, 0.0)));
+        assert!(mesh.points[3..].iter().any(|&q| close(q, p3(1.0, 1.0, 0.0))));
+        assert_eq!(mesh.triangles.len(), 4);
+    }
+
+    #[test]
+    fn decimate_keeps_flat_shapes_flat() {
+        let mesh = crate::primitives::plane(16, 16);
+        let out = decimate(&mesh, 64);
+        assert!(out.triangles.len() <= 64, "{} triangles", out.triangles.len());
+        assert!(out.points.iter().all(|p| p.z == 0.0));
+        // The open edges hold the outline, corners included.
+        let (min, max) = (out.bounds.min, out.bounds.max);
+        assert_eq!([min.x, min.y, max.x, max.y], [-1.0, -1.0, 1.0, 1.0]);
+        let area: f32 = (0..out.triangles.len()).map(|i| face_normal(&out.points, out.triangles[i]).magnitude() / 2.0).sum();
+        assert!((area - 4.0).abs() < 1e-4, "area {}", area);
+    }
+
+    #[test]
+    fn decimate_never_turns_faces_over() {
+        let mesh = crate::primitives::sphere(32, 16);
+        let out = decimate(&mesh, mesh.triangles.len() / 4);
+        assert!(out.triangles.len() < mesh.triangles.len() / 2);
+        out.clone().validate().unwrap();
+        for &t in &out.triangles {
+            let n = face_normal(&out.points, t);
+            let centre = (out.points[t[0] as usize] + out.points[t[1] as usize] + out.points[t[2] as usize]) * (1.0 / 3.0);
+            assert!(n.magnitude() < 1e-6 || n.dot(centre) > 0.0);
+        }
+    }
+}
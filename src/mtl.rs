@@ -0,0 +1,129 @@
+use crate::data::*;
+use crate::image::Image;
+
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+// Reads the materials in an MTL library. Texture paths are relative to the
+// library. Textures that fail to load are reported and left out, so the
+// material still draws in its diffuse colour.
+pub fn load(path: &Path) -> Result<Vec<Material>, String> {
+    let src = fs::read_to_string(path)
+        .map_err(|_| format!("file \"{}\" does not exist", path.display()))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse(&src, dir).map_err(|e| format!("bad MTL file \"{}\": {}", path.display(), e))
+}
+
+// Writes `materials` as an MTL library. Texture maps are left out, since
+// we don't keep the paths they came from.
+pub fn save(path: &Path, materials: &[Material]) -> Result<(), String> {
+    let mut out = String::new();
+    for m in materials {
+        let rgb = |c: [f32; 3]| format!("{} {} {}", c[0], c[1], c[2]);
+        out += &format!("newmtl {}\n", m.name);
+        out += &format!("Ka {}\nKd {}\nKs {}\n", rgb(m.ambient), rgb(m.diffuse), rgb(m.specular));
+        out += &format!("Ns {}\nd {}\nillum {}\n", m.shininess, m.opacity, m.illum);
+    }
+    fs::write(path, out).map_err(|e| format!("writing \"{}\": {}", path.display(), e))
+}
+
+fn parse(src: &str, dir: &Path) -> Result<Vec<Material>, String> {
+    let mut materials: Vec<Material> = vec![];
+    for (i, line) in src.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let mut words = line.split_whitespace();
+        let key = match words.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let args: Vec<&str> = words.collect();
+        let at = |e: String| format!("line {}: {}", i + 1, e);
+
+        if key == "newmtl" {
+            materials.push(Material::new(&args.join(" ")));
+            continue;
+        }
+        let m = match materials.last_mut() {
+            Some(m) => m,
+            // Statements before the first newmtl have nothing to apply to.
+            None => continue,
+        };
+        match key {
+            "Ka" => m.ambient = rgb(&args).map_err(at)?,
+            "Kd" => m.diffuse = rgb(&args).map_err(at)?,
+            "Ks" => m.specular = rgb(&args).map_err(at)?,
+            "Ns" => m.shininess = float(&args).map_err(at)?,
+            "d"  => m.opacity = float(&args).map_err(at)?,
+            "Tr" => m.opacity = 1.0 - float(&args).map_err(at)?,
+            "illum" => m.illum = float(&args).map_err(at)? as u32,
+            "map_Kd" => {
+                // Options such as `-s 1 1 1` may come first; the file is last.
+                let file = args.last().ok_or_else(|| at("expected a filename".to_string()))?;
+                let path = dir.join(file);
+                match Image::load(&path.to_string_lossy()) {
+                    Ok(image) => m.diffuse_map = Some(Rc::new(image)),
+                    Err(e) => eprintln!("warning: material \"{}\": {}", m.name, e),
+                }
+            },
+            _ => {},
+        }
+    }
+    Ok(materials)
+}
+
+fn float(args: &[&str]) -> Result<f32, String> {
+    let s = args.first().ok_or("expected a number")?;
+    s.parse().map_err(|e| format!("parsing \"{}\": {}", s, e))
+}
+
+// One value sets all three channels. Spectral and CIEXYZ colours aren't
+// supported.
+fn rgb(args: &[&str]) -> Result<[f32; 3], String> {
+    let xs = args.iter()
+        .map(|s| s.parse().map_err(|e| format!("parsing \"{}\": {}", s, e)))
+        .collect::<Result<Vec<f32>, String>>()?;
+    match *xs {
+        [x] => Ok([x, x, x]),
+        [r, g, b] => Ok([r, g, b]),
+        _ => Err(format!("expected 1 or 3 numbers, found {}", xs.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_materials() {
+        let src = "\
+Kd 1 1 1 # before any newmtl, so ignored
+newmtl brushed steel
+Kd 0.5 0.6 0.7
+Ks 1
+Ns 32
+d 0.5
+newmtl paint
+Tr 0.25
+illum 2
+";
+        let materials = parse(src, Path::new("")).unwrap();
+        assert_eq!(materials.len(), 2);
+        let (steel, paint) = (&materials[0], &materials[1]);
+        assert_eq!(steel.name, "brushed steel");
+        assert_eq!(steel.diffuse, [0.5, 0.6, 0.7]);
+        assert_eq!(steel.specular, [1.0; 3]);
+        assert_eq!((steel.shininess, steel.opacity), (32.0, 0.5));
+        assert_eq!(paint.name, "paint");
+        assert_eq!((paint.opacity, paint.illum), (0.75, 2));
+        assert_eq!(paint.diffuse, [0.8; 3]);
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let err = |src: &str| parse(src, Path::new("")).err().unwrap();
+        assert_eq!(err("newmtl a\nKd 1 1\n"), "line 2: expected 1 or 3 numbers, found 2");
+        assert!(err("newmtl a\nNs shiny\n").starts_with("line 2: parsing \"shiny\""));
+        assert_eq!(err("newmtl a\nmap_Kd\n"), "line 2: expected a filename");
+    }
+}
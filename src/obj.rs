@@ -0,0 +1,316 @@
+use crate::data::*;
+
+use crate::mtl;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Reads the polygonal subset of Wavefront OBJ: `v`, `vt`, `vn` and `f`, plus
+// `o`, `g` and `usemtl` for grouping and `mtllib` for materials. Faces with
+// more than three corners are triangulated as fans. Statements we don't draw
+// (curves, lines, smoothing groups, ...) are skipped.
+pub fn load(path: &str) -> Result<Mesh, String> {
+    let src = fs::read_to_string(path)
+        .map_err(|_| format!("file \"{}\" does not exist", path))?;
+    let (mut mesh, libraries) = parse(&src)
+        .map_err(|e| format!("bad OBJ file \"{}\": {}", path, e))?;
+
+    // Libraries are relative to the OBJ file. A missing one only costs us
+    // colours, so it isn't fatal.
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    for lib in libraries {
+        let lib = dir.join(lib);
+        if !lib.exists() {
+            eprintln!("warning: \"{}\" uses missing material library \"{}\"",
+                path, lib.display());
+            continue;
+        }
+        mesh.materials.extend(mtl::load(&lib)?);
+    }
+    Ok(mesh)
+}
+
+// Writes `mesh` as OBJ, with its normals and texture coordinates if it has
+// them. Groups become `g` statements with their `usemtl` names, and the
+// materials go in an MTL library of the same name next to it.
+pub fn save(path: &str, mesh: &Mesh) -> Result<(), String> {
+    let mut out = String::new();
+    if !mesh.materials.is_empty() {
+        let lib = Path::new(path).with_extension("mtl");
+        mtl::save(&lib, &mesh.materials)?;
+        let name = lib.file_name().map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+        out += &format!("mtllib {}\n", name);
+    }
+    for p in &mesh.points {
+        out += &format!("v {} {} {}\n", p.x, p.y, p.z);
+    }
+    for uv in mesh.uvs.iter().flatten() {
+        out += &format!("vt {} {}\n", uv.x, uv.y);
+    }
+    for n in mesh.normals.iter().flatten() {
+        out += &format!("vn {} {} {}\n", n.x, n.y, n.z);
+    }
+
+    // Every list shares the point numbering, so each corner repeats one
+    // index, counting from 1.
+    let corner = |v: u32| {
+        let v = v + 1;
+        match (&mesh.uvs, &mesh.normals) {
+            (Some(_), Some(_)) => format!("{}/{}/{}", v, v, v),
+            (Some(_), None) => format!("{}/{}", v, v),
+            (None, Some(_)) => format!("{}//{}", v, v),
+            (None, None) => v.to_string(),
+        }
+    };
+    for (i, t) in mesh.triangles.iter().enumerate() {
+        if let Some(g) = mesh.groups.iter().find(|g| g.start == i && g.count > 0) {
+            out += &format!("g {}\n", g.name);
+            if let Some(m) = &g.material { out += &format!("usemtl {}\n", m); }
+        } else if mesh.groups.iter().any(|g| g.count > 0 && g.start + g.count == i) {
+            out += "g default\n";
+        }
+        out += &format!("f {} {} {}\n", corner(t[0]), corner(t[1]), corner(t[2]));
+    }
+    fs::write(path, out).map_err(|e| format!("writing \"{}\": {}", path, e))
+}
+
+// Indices into the position, texture and normal lists of one face corner.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+struct Reader {
+    positions: Vec<Point3>,
+    uvs: Vec<Point2>,
+    normals: Vec<Point3>,
+
+    mesh: Mesh,
+    // Attributes of each mesh vertex, kept in step with its points until we
+    // know whether any corner had them.
+    vertex_uvs: Vec<Point2>,
+    vertex_normals: Vec<Point3>,
+    // Mesh vertex for each distinct corner seen so far.
+    vertices: HashMap<Corner, u32>,
+    any_uvs: bool,
+    any_normals: bool,
+
+    group: String,
+    material: Option<String>,
+    libraries: Vec<String>,
+}
+
+// Returns the mesh and the material libraries it refers to.
+fn parse(src: &str) -> Result<(Mesh, Vec<String>), String> {
+    let mut r = Reader {
+        positions: vec![],
+        uvs: vec![],
+        normals: vec![],
+        mesh: Mesh::new(),
+        vertex_uvs: vec![],
+        vertex_normals: vec![],
+        vertices: HashMap::new(),
+        any_uvs: false,
+        any_normals: false,
+        group: "default".to_string(),
+        material: None,
+        libraries: vec![],
+    };
+
+    let mut lines = src.lines().enumerate();
+    while let Some((i, line)) = lines.next() {
+        // A trailing backslash joins the next line onto this one.
+        let mut line = line.to_string();
+        while line.ends_with('\\') {
+            line.pop();
+            match lines.next() {
+                Some((_, next)) => { line.push(' '); line.push_str(next); },
+                None => break,
+            }
+        }
+        let line = line.split('#').next().unwrap();
+
+        let mut words = line.split_whitespace();
+        let key = match words.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let args: Vec<&str> = words.collect();
+        r.statement(key, &args).map_err(|e| format!("line {}: {}", i + 1, e))?;
+    }
+
+    if r.mesh.triangles.is_empty() && src.lines().next().map(str::trim) == Some("points") {
+        return Err(
+            "no faces; this looks like the points/triangles format, which RAWMESH reads"
+            .to_string());
+    }
+
+    if r.any_uvs { r.mesh.uvs = Some(r.vertex_uvs); }
+    if r.any_normals { r.mesh.normals = Some(r.vertex_normals); }
+    r.mesh.validate()?;
+    Ok((r.mesh, r.libraries))
+}
+
+impl Reader {
+    fn statement(&mut self, key: &str, args: &[&str]) -> Result<(), String> {
+        match key {
+            "v" => {
+                // An optional w, or a vertex colour, may follow.
+                let xs = floats(args, 3, 7)?;
+                self.positions.push(Point3 { x: xs[0], y: xs[1], z: xs[2] });
+            },
+            "vt" => {
+                let xs = floats(args, 1, 3)?;
+                self.uvs.push(Point2 { x: xs[0], y: xs.get(1).copied().unwrap_or(0.0) });
+            },
+            "vn" => {
+                let xs = floats(args, 3, 3)?;
+                self.normals.push(Point3 { x: xs[0], y: xs[1], z: xs[2] });
+            },
+            "f" => {
+                if args.len() < 3 {
+                    return Err(format!("face needs at least 3 corners, found {}", args.len()));
+                }
+                let corners = args.iter()
+                    .map(|c| self.corner(c))
+                    .collect::<Result<Vec<Corner>, String>>()?;
+                let vertices: Vec<u32> = corners.into_iter()
+                    .map(|c| self.vertex(c))
+                    .collect();
+                for i in 1..vertices.len() - 1 {
+                    self.mesh.triangles.push([vertices[0], vertices[i], vertices[i + 1]]);
+                }
+                self.extend_group();
+            },
+            "o" | "g" => {
+                self.group = if args.is_empty() { "default".to_string() } else { args.join(" ") };
+            },
+            "usemtl" => {
+                // Names may have spaces, as with `o`, `g` and `newmtl`.
+                self.material = (!args.is_empty()).then(|| args.join(" "));
+            },
+            "mtllib" => {
+                self.libraries.extend(args.iter().map(|s| s.to_string()));
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // Parses `v`, `v/vt`, `v//vn` or `v/vt/vn`. Indices count from 1, or back
+    // from the most recent element if negative.
+    fn corner(&self, s: &str) -> Result<Corner, String> {
+        let mut parts = s.split('/');
+        let index = |part: Option<&str>, len: usize, what: &str| -> Result<Option<usize>, String> {
+            let part = match part {
+                None | Some("") => return Ok(None),
+                Some(p) => p,
+            };
+            let i: isize = part.parse()
+                .map_err(|e| format!("parsing {} index \"{}\": {}", what, part, e))?;
+            let resolved = if i < 0 { len as isize + i } else { i - 1 };
+            if i == 0 || resolved < 0 || resolved >= len as isize {
+                return Err(format!("{} index {} out of range (have {})", what, i, len));
+            }
+            Ok(Some(resolved as usize))
+        };
+        let v = index(parts.next(), self.positions.len(), "vertex")?
+            .ok_or_else(|| format!("face corner \"{}\" has no vertex", s))?;
+        let vt = index(parts.next(), self.uvs.len(), "texture")?;
+        let vn = index(parts.next(), self.normals.len(), "normal")?;
+        if parts.next().is_some() {
+            return Err(format!("bad face corner \"{}\"", s));
+        }
+        Ok((v, vt, vn))
+    }
+
+    fn vertex(&mut self, c: Corner) -> u32 {
+        if let Some(&i) = self.vertices.get(&c) { return i; }
+        let (v, vt, vn) = c;
+        let i = self.mesh.points.len() as u32;
+        self.mesh.points.push(self.positions[v]);
+        self.vertex_uvs.push(vt.map_or(Point2 { x: 0.0, y: 0.0 }, |i| self.uvs[i]));
+        self.vertex_normals.push(
+            vn.map_or(Point3 { x: 0.0, y: 0.0, z: 0.0 }, |i| self.normals[i]));
+        self.any_uvs |= vt.is_some();
+        self.any_normals |= vn.is_some();
+        self.vertices.insert(c, i);
+        i
+    }
+
+    // Accounts for the triangles just added in the current group, starting a
+    // new one if the name or material changed.
+    fn extend_group(&mut self) {
+        let end = self.mesh.triangles.len();
+        if let Some(g) = self.mesh.groups.last_mut() {
+            if g.name == self.group && g.material == self.material {
+                g.count = end - g.start;
+                return;
+            }
+        }
+        let start = self.mesh.groups.last().map_or(0, |g| g.start + g.count);
+        self.mesh.groups.push(MeshGroup {
+            name: self.group.clone(),
+            material: self.material.clone(),
+            start,
+            count: end - start,
+        });
+    }
+}
+
+fn floats(args: &[&str], min: usize, max: usize) -> Result<Vec<f32>, String> {
+    if args.len() < min || args.len() > max {
+        let expected = if min == max { min.to_string() } else { format!("{} to {}", min, max) };
+        return Err(format!("expected {} numbers, found {}", expected, args.len()));
+    }
+    args.iter()
+        .map(|s| s.parse().map_err(|e| format!("parsing \"{}\": {}", s, e)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUBE_SIDE: &str = "\
+mtllib side.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vn 0 0 1
+# A quad, split into a fan.
+g front face
+usemtl brushed steel
+f 1/1/1 2/1/1 3/1/1 4/1/1
+g back
+usemtl paint
+f -4//1 -2//1 -3//1
+";
+
+    #[test]
+    fn reads_faces_and_groups() {
+        let (mesh, libraries) = parse(CUBE_SIDE).unwrap();
+        assert_eq!(libraries, ["side.mtl"]);
+        assert_eq!(mesh.triangles, [[0, 1, 2], [0, 2, 3], [4, 5, 6]]);
+        // The back face's corners have no uv, so they're new vertices.
+        assert_eq!(mesh.points.len(), 7);
+        assert_eq!(mesh.uvs.as_ref().map(|uvs| uvs.len()), Some(7));
+        let groups: Vec<_> = mesh.groups.iter()
+            .map(|g| (g.name.as_str(), g.material.as_deref(), g.start, g.count))
+            .collect();
+        assert_eq!(groups, [
+            ("front face", Some("brushed steel"), 0, 2),
+            ("back", Some("paint"), 2, 1),
+        ]);
+    }
+
+    #[test]
+    fn rejects_bad_faces() {
+        let err = |src: &str| parse(src).err().unwrap();
+        assert!(err("v 0 0 0\nf 1 1\n").contains("at least 3 corners"));
+        assert!(err("v 0 0 0\nf 1 1 2\n").contains("vertex index 2 out of range"));
+        assert!(err("v 0 0 0\nf 1 1 -2\n").contains("vertex index -2 out of range"));
+        assert!(err("v 0 0 0\nf 1 1 1/0\n").contains("texture index 0 out of range"));
+        assert!(err("v 0 0\n").contains("expected"));
+    }
+}
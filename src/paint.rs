@@ -0,0 +1,102 @@
+use crate::data::*;
+
+use std::f32::consts::PI;
+
+// What the fill routines colour each pixel with, and how opaque it is, from
+// 0 to 1.
+#[derive(Debug, Clone)]
+pub enum Paint {
+    Solid(Color, f32),
+    Gradient(Gradient),
+}
+
+impl Paint {
+    #[inline]
+    pub fn at(&self, x: isize, y: isize) -> (Color, f32) {
+        match self {
+            Paint::Solid(c, alpha) => (*c, *alpha),
+            Paint::Gradient(g) => g.at(Point2 { x: x as f32 + 0.5, y: y as f32 + 0.5 }),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Spread { Pad, Repeat, Reflect }
+
+// Gradient geometry, in screen space.
+#[derive(Debug, Copy, Clone)]
+pub enum Shape {
+    Linear { from: Point2, to: Point2 },
+    Radial { center: Point2, radius: f32 },
+    // `angle` is where the sweep starts, in radians clockwise from +x.
+    Conic { center: Point2, angle: f32 },
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Stop {
+    pub offset: f32,
+    pub color: Color,
+    // From 0 to 1.
+    pub alpha: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    pub shape: Shape,
+    pub spread: Spread,
+    // Sorted by offset. There are always at least two.
+    pub stops: Vec<Stop>,
+}
+
+impl Gradient {
+    pub fn at(&self, p: Point2) -> (Color, f32) {
+        let t = match self.shape {
+            Shape::Linear { from, to } => {
+                let (dx, dy) = (to.x - from.x, to.y - from.y);
+                let len2 = dx*dx + dy*dy;
+                if len2 == 0.0 { 0.0 }
+                else { ((p.x - from.x) * dx + (p.y - from.y) * dy) / len2 }
+            },
+            Shape::Radial { center, radius } => {
+                let (dx, dy) = (p.x - center.x, p.y - center.y);
+                if radius == 0.0 { 1.0 } else { (dx*dx + dy*dy).sqrt() / radius }
+            },
+            Shape::Conic { center, angle } => {
+                let a = (p.y - center.y).atan2(p.x - center.x) - angle;
+                a.rem_euclid(2.0 * PI) / (2.0 * PI)
+            },
+        };
+        self.color_at(self.spread(t))
+    }
+
+    fn spread(&self, t: f32) -> f32 {
+        match self.spread {
+            Spread::Pad => t.clamp(0.0, 1.0),
+            Spread::Repeat => t.rem_euclid(1.0),
+            Spread::Reflect => {
+                let t = t.rem_euclid(2.0);
+                if t > 1.0 { 2.0 - t } else { t }
+            },
+        }
+    }
+
+    fn color_at(&self, t: f32) -> (Color, f32) {
+        let first = self.stops[0];
+        if t <= first.offset { return (first.color, first.alpha); }
+        for w in self.stops.windows(2) {
+            let (a, b) = (w[0], w[1]);
+            if t <= b.offset {
+                let span = b.offset - a.offset;
+                let p = if span == 0.0 { 1.0 } else { (t - a.offset) / span };
+                return (lerp_color(a.color, b.color, p), a.alpha + (b.alpha - a.alpha) * p);
+            }
+        }
+        let last = self.stops[self.stops.len() - 1];
+        (last.color, last.alpha)
+    }
+}
+
+fn lerp_color(c1: Color, c2: Color, t: f32) -> Color {
+    let l = |a: u8, b: u8| (a as f32 * (1.0 - t) + b as f32 * t).round() as u8;
+    Color { r: l(c1.r, c2.r), g: l(c1.g, c2.g), b: l(c1.b, c2.b) }
+}
@@ -1,22 +1,125 @@
 use crate::data::*;
+use crate::easing::Easing;
+use crate::error::{ErrorKind, ParseError, ParseErrors};
+use crate::expr::{self, Expr};
+use crate::gltf;
+use crate::image::{Filter, Fit, Image};
+use crate::keyframes::{self, Interp, Key};
+use crate::lexer;
+use crate::lod::Lods;
+use crate::meshops::{self, MeshOp};
+use crate::obj;
+use crate::paint::Spread;
+use crate::ply;
+use crate::primitives;
+use crate::stl;
+use crate::text::{self, Align, Font, HAlign, TextPart, VAlign};
+use crate::transform::Transform;
 
-use std::fs::File;
+use std::cell::Cell;
+use std::fs::{self, File};
 use std::io::{self, BufRead};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct Scene {
     pub commands: Vec<Command>,
-    vars: HashMap<String, Vec<Animation>>
+    pub background: Background,
+    // Vars by scope; see `Loader`.
+    vars: Vec<HashMap<String, Var>>,
+    parents: Vec<Option<usize>>,
+    // Where vars are looked up from, changed while a group is drawn.
+    scope: Cell<usize>,
+}
+
+// An animated var: its animations in time order, and what it does outside
+// them.
+#[derive(Debug, Default)]
+struct Var {
+    anims: Vec<Animation>,
+    // Before the first animation and after the last. Unset, a var has no
+    // value before it starts and holds its last value after it ends.
+    pre: Option<Extrapolate>,
+    post: Option<Extrapolate>,
+    // Used before the start when there's no `pre`, and throughout when
+    // there are no animations.
+    default: Option<f32>,
+    // For a DEFINE parameter, the argument it was given in USE, and the
+    // scope that's worked out in.
+    arg: Option<(Val, usize)>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Extrapolate {
+    // Holds the value at the end.
+    Constant,
+    // Repeats the animations.
+    Cycle,
+    // Repeats them, each time starting from where the last one ended.
+    CycleOffset,
+    // Repeats them, every other time backwards.
+    PingPong,
+    // Carries on at the speed it was going at the end.
+    Linear,
+}
+
+// How the screen is prepared before each frame is drawn.
+#[derive(Debug)]
+pub enum Background {
+    // Leave the previous frame in place, for trails and accumulation.
+    None,
+    Solid(ValColor),
+    Gradient { vertical: bool, from: ValColor, to: ValColor },
+    Image { image: Image, fit: Fit },
 }
 
 #[derive(Debug)]
 struct Animation {
-    from: f32,
-    to: f32,
     t1: f32,
     t2: f32,
+    motion: Motion,
+}
+
+#[derive(Debug)]
+enum Motion {
+    // ANIMATE: from one value to another.
+    Tween { from: f32, to: f32, ease: Easing },
+    // KEYFRAMES: through each key in turn.
+    Keys(Vec<Key>),
+    // One channel of a colour var's ANIMATE, blended in `space`. Alpha is
+    // a plain Tween.
+    Color { from: Color, to: Color, space: ColorSpace, channel: usize, ease: Easing },
+}
+
+// What an animated var holds. Vec3 and colour vars are stored as a number
+// var per component, with colours' alpha as the fourth.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Kind {
+    Number,
+    Vec3,
+    Color,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Number => "number",
+            Kind::Vec3 => "vec3",
+            Kind::Color => "color",
+        }
+    }
+
+    // The number vars a var of this kind is stored in.
+    fn parts(self, name: &str) -> Vec<String> {
+        let components: &[&str] = match self {
+            Kind::Number => return vec![name.to_string()],
+            Kind::Vec3 => &["x", "y", "z"],
+            Kind::Color => &["r", "g", "b", "a"],
+        };
+        components.iter().map(|c| format!("{}.{}", name, c)).collect()
+    }
 }
 
 impl Animation {
@@ -26,36 +129,114 @@ impl Animation {
         (other.t1 < self.t1 && self.t1 < other.t2) ||
         (other.t1 < self.t2 && self.t2 < other.t2)
     }
+
+    fn value_at(&self, time: f32) -> f32 {
+        match &self.motion {
+            Motion::Tween { from, to, ease } => {
+                let p = (time - self.t1) / (self.t2 - self.t1);
+                lerp(*from, *to, ease.apply(p))
+            },
+            Motion::Keys(keys) => keyframes::value_at(keys, time),
+            Motion::Color { from, to, space, channel, ease } => {
+                let p = (time - self.t1) / (self.t2 - self.t1);
+                from.mix(*to, ease.apply(p), *space)[*channel]
+            },
+        }
+    }
 }
 
 impl Scene {
     fn eval_at(&self, time: f32, val: &Val) -> Result<f32, String> {
-        let var = match val {
-            Val::Raw(x) => return Ok(*x),
-            Val::Var(s) => s,
+        match val {
+            Val::Raw(x) => Ok(*x),
+            Val::Var(s) => self.var_at(time, s),
+            Val::Expr(e) => e.eval(time, &|var| self.var_at(time, var)),
+        }
+    }
+
+    // Runs `f` with vars looked up from `scope`, as the commands of a group
+    // need.
+    pub fn in_scope<T>(&self, scope: usize, f: impl FnOnce() -> T) -> T {
+        let outer = self.scope.replace(scope);
+        let out = f();
+        self.scope.set(outer);
+        out
+    }
+
+    // The var `name` as seen from the current scope.
+    fn var(&self, name: &str) -> Option<&Var> {
+        scope_chain(&self.parents, self.scope.get()).find_map(|s| self.vars[s].get(name))
+    }
+
+    fn var_at(&self, time: f32, name: &str) -> Result<f32, String> {
+        let var = self.var(name).ok_or_else(|| {
+            // Vec3 and colour vars only exist as their components.
+            let has = |c: &str| self.var(&format!("{}.{}", name, c)).is_some();
+            if has("x") {
+                format!("var \"{0}\" is a vec3; use {0}.x, {0}.y or {0}.z", name)
+            } else if has("r") {
+                format!("var \"{0}\" is a color; use {0}.r, {0}.g, {0}.b or {0}.a", name)
+            } else {
+                format!("var \"{}\" not defined", name)
+            }
+        })?;
+        if let Some((val, scope)) = &var.arg {
+            return self.in_scope(*scope, || self.eval_at(time, val));
+        }
+        let (start, end) = match (var.anims.first(), var.anims.last()) {
+            (Some(first), Some(last)) => (first.t1, last.t2),
+            _ => return var.default.ok_or(format!("var \"{}\" has no animations", name)),
         };
+        let mode = if time < start { var.pre } else if time > end { var.post } else { None };
+        let at = |time: f32| Scene::sample(var, name, time);
+
+        let period = end - start;
+        match mode {
+            None => at(time),
+            Some(Extrapolate::Linear) if period > 0.0 => {
+                let h = period * 1e-3;
+                let (edge, slope) = if time < start {
+                    (start, (at(start + h)? - at(start)?) / h)
+                } else {
+                    (end, (at(end)? - at(end - h)?) / h)
+                };
+                Ok(at(edge)? + slope * (time - edge))
+            },
+            Some(Extrapolate::Cycle) | Some(Extrapolate::CycleOffset) | Some(Extrapolate::PingPong)
+                if period > 0.0 =>
+            {
+                let n = ((time - start) / period).floor();
+                let local = time - start - n * period;
+                match mode {
+                    Some(Extrapolate::CycleOffset) =>
+                        Ok(at(start + local)? + n * (at(end)? - at(start)?)),
+                    Some(Extrapolate::PingPong) if n.rem_euclid(2.0) == 1.0 => at(end - local),
+                    _ => at(start + local),
+                }
+            },
+            // Everything else, and any repeat of an instant, holds the end.
+            Some(_) => at(time.clamp(start, end)),
+        }
+    }
 
+    // The var's value at `time`, holding the last animation's end value in
+    // gaps and after the end.
+    fn sample(var: &Var, name: &str, time: f32) -> Result<f32, String> {
         let mut last_time = -1.0;
         let mut last_val = None;
-        let anims = self.vars.get(var)
-            .ok_or(format!("var \"{}\" not defined", var))?;
-        for anim in anims {
-            match *anim {
-                Animation { from, to, t1, t2 } =>
-                    if t1 <= time && time <= t2 {
-                        let p = (time - t1) / (t2 - t1);
-                        return Ok(lerp(from, to, p));
-                    } else if time > t2 && t2 > last_time {
-                        last_time = t2;
-                        last_val = Some(to);
-                    }
+        for anim in &var.anims {
+            if anim.t1 <= time && time <= anim.t2 {
+                return Ok(anim.value_at(time));
+            } else if time > anim.t2 && anim.t2 > last_time {
+                last_time = anim.t2;
+                last_val = Some(anim.value_at(anim.t2));
             }
         }
 
-        match last_val {
+        match last_val.or(var.default) {
             Some(x) => Ok(x),
             None => Err(
-                format!("var \"{}\" has no matching animations at time {:?}", var, time)),
+                format!("var \"{}\" has no matching animations at time {:?}", name, time)),
         }
     }
 }
@@ -72,6 +253,17 @@ impl Eval for Val {
     }
 }
 
+impl Eval for ValColor {
+    // With the alpha from 0 to 1, as paints take it.
+    type Out = (Color, f32);
+    fn eval_at(&self, t: f32, scene: &Scene) -> Result<Self::Out, String> {
+        let c = |v: &Val| -> Result<u8, String> {
+            Ok(v.eval_at(t, scene)?.round().clamp(0.0, 255.0) as u8)
+        };
+        Ok((Color { r: c(&self.r)?, g: c(&self.g)?, b: c(&self.b)? }, c(&self.a)? as f32 / 255.0))
+    }
+}
+
 impl Eval for ValPoint3 {
     type Out = Point3;
     fn eval_at(&self, t: f32, scene: &Scene) ->  Result<Self::Out, String> {
@@ -93,6 +285,7 @@ fn lerp(y1: f32, y2: f32, t: f32) -> f32 {
 pub enum Val {
     Raw(f32),
     Var(String),
+    Expr(Box<Expr>),
 }
 
 #[derive(Debug)]
@@ -102,6 +295,60 @@ pub struct ValPoint3 {
     z: Val
 }
 
+// Channels, alpha included, are clamped to [0, 255] when evaluated.
+#[derive(Debug)]
+pub struct ValColor {
+    r: Val,
+    g: Val,
+    b: Val,
+    a: Val,
+}
+
+impl ValColor {
+    // From 3 or 4 values; without an alpha the colour is opaque.
+    fn new(xs: &[Val]) -> ValColor {
+        ValColor {
+            r: xs[0].clone(),
+            g: xs[1].clone(),
+            b: xs[2].clone(),
+            a: xs.get(3).cloned().unwrap_or(Val::Raw(255.0)),
+        }
+    }
+}
+
+// An image centred on `p`. Unless it's a billboard, the image lies in the
+// xy plane with one scene unit per pixel and goes through the transform;
+// billboards stay upright and unscaled on screen.
+#[derive(Debug)]
+pub struct Sprite {
+    pub image: Image,
+    pub p: ValPoint3,
+    pub scale: Val,
+    pub rotate: Val,
+    pub alpha: Val,
+    pub filter: Filter,
+    pub billboard: bool,
+    // Columns and rows of the sprite sheet, and which cell to draw. Cells
+    // are numbered left to right, top to bottom, and wrap around.
+    pub sheet: (usize, usize),
+    pub frame: Val,
+}
+
+// Gradient geometry, in scene coordinates. See `paint::Shape`.
+#[derive(Debug)]
+pub enum ValShape {
+    Linear { from: ValPoint3, to: ValPoint3 },
+    Radial { center: ValPoint3, radius: Val },
+    Conic { center: ValPoint3, angle: Val },
+}
+
+// A gradient stop, with its colour worked out each frame.
+#[derive(Debug)]
+pub struct ValStop {
+    pub offset: f32,
+    pub color: ValColor,
+}
+
 // ====================================================================== //
 // ============================== COMMANDS ============================== //
 // ====================================================================== //
@@ -111,74 +358,718 @@ pub enum Command {
     Point { p: ValPoint3, rad: Val },
     Line(ValPoint3, ValPoint3),
     Triangle(ValPoint3, ValPoint3, ValPoint3),
-    Mesh { points: Vec<Point3>, triangles: Vec<usize> },
+    Mesh(Lods),
+    Model(gltf::Model),
+    Sprite(Sprite),
+    Text {
+        p: ValPoint3,
+        text: Vec<TextPart>,
+        size: Val,
+        align: Align,
+        // When false, `p` is in screen pixels and ignores the transform.
+        projected: bool,
+        font: Font,
+    },
 
     Identity,
     Translate(Val, Val, Val),
     Scale(Val, Val, Val),
     Rotate { theta: Val, v: ValPoint3 },
+    // Save the transform and paint, and bring back the last ones saved.
+    // Every POP has a PUSH before it in the same group.
+    Push,
+    Pop,
+
+    Color(ValColor),
+    Gradient { shape: ValShape, spread: Spread, stops: Vec<ValStop> },
 
-    Color(Color),
+    // A BEGIN block, or the commands of an INCLUDEd file or USEd macro.
+    // They start with the transform and paint in effect, so groups inside
+    // groups move with them, but changes to those end with the group. Their
+    // vars are looked up from `scope`. The name says where draw errors are.
+    Group { name: String, scope: usize, commands: Vec<Command> },
 }
 
-pub fn load_scene(path: &str) -> Result<Scene, String> {
-    let mut lines = read_lines(path)
-        .map_err(|_| { format!("file \"{}\" does not exist", path) })?;
+impl Command {
+    // The transform in effect after this command, given the one before it.
+    // Only the transform commands change it.
+    pub fn transform(&self, tr: Transform, t: f32, scene: &Scene) -> Result<Transform, String> {
+        Ok(match self {
+            Command::Scale(x, y, z) =>
+                Transform::scale(x.eval_at(t, scene)?, y.eval_at(t, scene)?, z.eval_at(t, scene)?) * tr,
+            Command::Translate(x, y, z) =>
+                Transform::translate(x.eval_at(t, scene)?, y.eval_at(t, scene)?, z.eval_at(t, scene)?) * tr,
+            Command::Rotate { theta, v } =>
+                Transform::rotate(theta.eval_at(t, scene)?, v.eval_at(t, scene)?) * tr,
+            Command::Identity => Transform::IDENTITY,
+            _ => tr,
+        })
+    }
+}
+
+pub fn load_scene(path: &str) -> Result<Scene, ParseErrors> {
+    let mut loader = Loader {
+        commands: vec![],
+        background: Background::Solid(ValColor::new(&[Val::Raw(0.0), Val::Raw(0.0), Val::Raw(0.0)])),
+        vars: vec![HashMap::new()],
+        meshes: vec![HashMap::new()],
+        kinds: vec![HashMap::new()],
+        macros: vec![HashMap::new()],
+        parents: vec![None],
+        scope: 0,
+        dir: PathBuf::new(),
+        files: vec![],
+        using: vec![],
+        pushes: vec![],
+        errors: vec![],
+    };
+    if let Err(kind) = loader.read_file(path) {
+        loader.errors.push(ParseError::file(path, kind));
+    }
 
-    let mut commands: Vec<Command> = vec![];
-    let mut vars: HashMap<String, Vec<Animation>> = HashMap::new();
+    if !loader.errors.is_empty() { return Err(ParseErrors(loader.errors)); }
+    Ok(Scene {
+        commands: loader.commands,
+        background: loader.background,
+        vars: loader.vars,
+        parents: loader.parents,
+        scope: Cell::new(0),
+    })
+}
 
-    'foo: loop {
-        let line = match lines.next() {
-            None => break 'foo,
-            Some(Err(e)) => return Err(format!("bad line parse: {}", e)),
-            Some(Ok(l)) => l
-        };
+// A scene as it's read in, with the names defined so far.
+struct Loader {
+    commands: Vec<Command>,
+    background: Background,
+    // Vars, meshes and var kinds by scope. The main file is scope 0 and
+    // each INCLUDE gets a new one inside the scope it's read in.
+    vars: Vec<HashMap<String, Var>>,
+    meshes: Vec<HashMap<String, Lods>>,
+    kinds: Vec<HashMap<String, Kind>>,
+    macros: Vec<HashMap<String, Rc<Macro>>>,
+    parents: Vec<Option<usize>>,
+    // The scope and folder of the file being read.
+    scope: usize,
+    dir: PathBuf,
+    // The files being read and the macros being used, outermost first, to
+    // catch INCLUDE and USE cycles.
+    files: Vec<(PathBuf, String)>,
+    // Each macro comes with a note naming the USE it was used by, for the
+    // errors in its lines.
+    using: Vec<(Rc<Macro>, String)>,
+    // The number and text of each line with a PUSH not yet POPped in the
+    // group being read.
+    pushes: Vec<(usize, String)>,
+    errors: Vec<ParseError>,
+}
 
-        if line == "" { continue }
-        let (cmd, rest) = line.trim().split_once(" ").unwrap_or((&line, ""));
-        match &*cmd.to_lowercase() {
-            "#" => continue 'foo,
-
-            "point"    => commands.push(parse_cmd_point(rest)?),
-            "line"     => commands.push(parse_cmd_line(rest)?),
-            "triangle" => commands.push(parse_cmd_triangle(rest)?),
-            "mesh"     => commands.push(parse_cmd_mesh(rest)?),
-
-            "identity"  => commands.push(Command::Identity),
-            "translate" => commands.push(parse_cmd_translate(rest)?),
-            "scale"     => commands.push(parse_cmd_scale(rest)?),
-            "rotate"    => commands.push(parse_cmd_rotate(rest)?),
-
-            "color"     => commands.push(parse_cmd_color(rest)?),
-            "animate"   => {
-                let (var, animation) = parse_cmd_animate(rest)?;
-                if !vars.contains_key(&var) {
-                    vars.insert(var.clone(), vec![]);
-                }
-                let anims = vars.get_mut(&var).unwrap();
-                for i in 0..anims.len() {
-                    let other = &anims[i];
-                    if animation.t2 < other.t1 {
-                        anims.insert(i, animation);
-                        continue 'foo;
+// A DEFINE block, kept as its lines until it's used.
+#[derive(Debug)]
+struct Macro {
+    name: String,
+    params: Vec<String>,
+    // Each line with its number in `file`.
+    body: Vec<(usize, String)>,
+    file: String,
+    dir: PathBuf,
+    // Where it was defined, which is what its lines see.
+    scope: usize,
+}
+
+impl Loader {
+    // Reads the file at `path` in the current scope. Problems with its lines
+    // are added to `errors`; only not being able to read it at all is
+    // returned.
+    fn read_file(&mut self, path: &str) -> Result<(), ErrorKind> {
+        let missing = || ErrorKind::Io(format!("file \"{}\" does not exist", path));
+        let id = fs::canonicalize(path).map_err(|_| missing())?;
+        if let Some(i) = self.files.iter().position(|(f, _)| *f == id) {
+            let cycle: Vec<&str> = self.files[i..].iter().map(|(_, name)| name.as_str()).collect();
+            return Err(format!("INCLUDE cycle: {} -> {}", cycle.join(" -> "), path).into());
+        }
+        let lines = SourceLines::open(path).map_err(|_| missing())?;
+        let dir = Path::new(path).parent().unwrap_or(Path::new("")).to_path_buf();
+        let outer_dir = std::mem::replace(&mut self.dir, dir);
+        self.files.push((id, path.to_string()));
+        self.read(lines);
+        self.files.pop();
+        self.dir = outer_dir;
+        Ok(())
+    }
+
+    // Reads each of `lines`, adding any problems to `errors` and carrying
+    // on.
+    fn read(&mut self, mut lines: SourceLines) {
+        let file = &lines.file.clone();
+        let mut next = None;
+        loop {
+            let line = match next.take().or_else(|| lines.next()) {
+                None => break,
+                Some(Err(e)) => {
+                    self.errors.push(ParseError::file(file, ErrorKind::Io(format!("bad line parse: {}", e))));
+                    break;
+                },
+                Some(Ok(l)) => l
+            };
+            let number = lines.number;
+            if let Err(kind) = self.line(&line, &mut lines) {
+                let error = if lines.number == number {
+                    // Point at the arguments, or at the command if it's
+                    // unknown or has none.
+                    let start = line.len() - line.trim_start().len();
+                    let cmd_end = start + line.trim().find(char::is_whitespace).unwrap_or(line.trim().len());
+                    // An expression left open, as on a line continued
+                    // with \, likely goes on over the next lines. They
+                    // can't start with a command, so skip them rather than
+                    // read its tail as one.
+                    let mut open = lexer::open_parens(&line);
+                    while open > 0 {
+                        match lines.next() {
+                            Some(Ok(l)) if !l.trim_start().starts_with(char::is_alphabetic) =>
+                                open = lexer::paren_depth(&l, open),
+                            other => {
+                                next = other;
+                                break;
+                            },
+                        }
                     }
-                    if animation.overlaps(&other) {
-                        return Err(
-                            format!("animation for var \"{}\" overlaps with another", var));
+                    match kind {
+                        ErrorKind::UnknownCommand(_) =>
+                            ParseError::at(kind, file, number, &line, start, cmd_end),
+                        // Lexer errors carry a caret of their own.
+                        _ if line[cmd_end..].trim().is_empty() =>
+                            locate(kind, file, number, &line, start),
+                        _ => locate(kind, file, number, &line, cmd_end),
                     }
+                } else {
+                    // A block went wrong somewhere inside; skip to its end.
+                    let (inner, inner_number) = (lines.last.clone(), lines.number);
+                    let start = inner.len() - inner.trim_start().len();
+                    if !inner.trim().eq_ignore_ascii_case("end") {
+                        while let Some(Ok(l)) = lines.next() {
+                            if l.trim().eq_ignore_ascii_case("end") { break; }
+                        }
+                    }
+                    locate(kind, file, inner_number, &inner, start)
+                };
+                self.report(error);
+            }
+        }
+        // Transforms and colours can't be PUSHed past the end of a group.
+        for (number, line) in std::mem::take(&mut self.pushes) {
+            let start = line.len() - line.trim_start().len();
+            let end = start + line.trim().find(char::is_whitespace).unwrap_or(line.trim().len());
+            let kind = ErrorKind::Invalid("PUSH without a POP after it".to_string());
+            self.report(ParseError::at(kind, file, number, &line, start, end));
+        }
+    }
+
+    // Adds `error` to `errors`, saying which USEs got to it, innermost first.
+    // A macro used more than once reports its mistakes once, noting every
+    // USE.
+    fn report(&mut self, mut error: ParseError) {
+        let notes = self.using.iter().rev().map(|(_, note)| note.clone());
+        let same = |e: &&mut ParseError| e.kind.to_string() == error.kind.to_string()
+            && e.span.file == error.span.file && e.span.line == error.span.line && e.span.col == error.span.col;
+        match self.errors.iter_mut().find(same) {
+            Some(e) => for note in notes {
+                if !e.notes.contains(&note) { e.notes.push(note); }
+            },
+            None => {
+                error.notes.extend(notes);
+                self.errors.push(error);
+            },
+        }
+    }
+
+    // Reads the file at `path` in a new scope, as a group of its own.
+    fn include(&mut self, name: &str, path: &str) -> Result<(), ErrorKind> {
+        let scope = self.parents.len();
+        self.parents.push(Some(self.scope));
+        self.vars.push(HashMap::new());
+        self.meshes.push(HashMap::new());
+        self.kinds.push(HashMap::new());
+        self.macros.push(HashMap::new());
+        self.group(name, scope, |loader| loader.read_file(path))
+    }
+
+    // Runs `read` on an empty command list, then adds what it read as a
+    // group called `name`, with its vars in `scope`.
+    fn group<T>(&mut self, name: &str, scope: usize, read: impl FnOnce(&mut Loader) -> T) -> T {
+        let outer_scope = std::mem::replace(&mut self.scope, scope);
+        let outer_commands = std::mem::take(&mut self.commands);
+        let outer_pushes = std::mem::take(&mut self.pushes);
+        let result = read(self);
+        let commands = std::mem::replace(&mut self.commands, outer_commands);
+        self.pushes = outer_pushes;
+        self.scope = outer_scope;
+        self.commands.push(Command::Group { name: name.to_string(), scope, commands });
+        result
+    }
+
+    // BEGIN name
+    //
+    // Followed by lines closed by END, drawn as a group; see
+    // `Command::Group`. They share vars with the lines around them.
+    fn begin(&mut self, rest: &str, lines: &mut SourceLines) -> Result<(), String> {
+        let start = (lines.number, lines.last.clone());
+        let body = block_lines("begin", lines)?;
+        (lines.number, lines.last) = start;
+        let name = rest.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("expected BEGIN name, found \"{}\"", name));
+        }
+        let file = lines.file.clone();
+        self.group(name, self.scope, |loader| loader.read(SourceLines::stored(&file, body)));
+        Ok(())
+    }
+
+    // DEFINE name(param, ...)
+    //
+    // Followed by the lines of the macro, closed by END. They're read each
+    // time it's used, as a group of their own; see `use_macro`.
+    fn define(&mut self, rest: &str, lines: &mut SourceLines) -> Result<(), String> {
+        let start = (lines.number, lines.last.clone());
+        let body = block_lines("define", lines)?;
+        // Anything wrong from here on is about the first line.
+        (lines.number, lines.last) = start;
+
+        let (name, params) = parse_call(rest.trim())?;
+        let params = params.iter().map(|p| {
+            if p.is_empty() || !p.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format!("bad parameter name \"{}\"", p));
+            }
+            Ok(p.to_string())
+        }).collect::<Result<Vec<String>, _>>()?;
+        if let Some(p) = params.iter().enumerate().find_map(|(i, p)| params[..i].contains(p).then_some(p)) {
+            return Err(format!("parameter \"{}\" is given twice", p));
+        }
+
+        let m = Macro { name: name.to_string(), params, body, file: lines.file.clone(), dir: self.dir.clone(), scope: self.scope };
+        if self.macros[self.scope].insert(name.to_string(), Rc::new(m)).is_some() {
+            return Err(format!("macro \"{}\" is defined twice", name));
+        }
+        Ok(())
+    }
+
+    // USE name(arg, ...)
+    //
+    // Draws the macro's lines with each parameter standing for its
+    // argument, which is worked out here each frame. Vec3 and colour vars
+    // can be passed whole. The lines see the vars where the macro was
+    // defined, and like an INCLUDE keep their transforms and colours to
+    // themselves.
+    fn use_macro(&mut self, rest: &str, lines: &SourceLines) -> Result<(), String> {
+        let (name, args) = parse_call(rest.trim())?;
+        let m = self.scopes().find_map(|s| self.macros[s].get(name))
+            .ok_or_else(|| format!("macro \"{}\" is not defined", name))?
+            .clone();
+        if args.len() != m.params.len() {
+            return Err(format!("{} takes {} argument{}, found {}",
+                name, m.params.len(), if m.params.len() == 1 { "" } else { "s" }, args.len()));
+        }
+        if let Some(i) = self.using.iter().position(|(u, _)| Rc::ptr_eq(u, &m)) {
+            let cycle: Vec<&str> = self.using[i..].iter().map(|(u, _)| u.name.as_str()).collect();
+            return Err(format!("USE cycle: {} -> {}", cycle.join(" -> "), name));
+        }
+
+        let scope = self.parents.len();
+        let mut vars = HashMap::new();
+        let mut kinds = HashMap::new();
+        for (param, arg) in m.params.iter().zip(&args) {
+            let kind = self.kind(arg).unwrap_or(Kind::Number);
+            let vals = match kind {
+                Kind::Number => vec![parse_val(arg)?],
+                _ => kind.parts(arg).into_iter().map(Val::Var).collect(),
+            };
+            for (part, val) in kind.parts(param).into_iter().zip(vals) {
+                vars.insert(part, Var { arg: Some((val, self.scope)), ..Var::default() });
+            }
+            kinds.insert(param.clone(), kind);
+        }
+        self.parents.push(Some(m.scope));
+        self.vars.push(vars);
+        self.kinds.push(kinds);
+        self.meshes.push(HashMap::new());
+        self.macros.push(HashMap::new());
+
+        let outer_dir = std::mem::replace(&mut self.dir, m.dir.clone());
+        let note = format!("note: in {} at {}:{}", lines.last.trim(), lines.file, lines.number);
+        self.using.push((m.clone(), note));
+        self.group(name, scope, |loader| loader.read(SourceLines::stored(&m.file, m.body.clone())));
+        self.using.pop();
+        self.dir = outer_dir;
+        Ok(())
+    }
+
+    // The current scope and the ones around it, innermost first.
+    fn scopes(&self) -> impl Iterator<Item = usize> + '_ {
+        scope_chain(&self.parents, self.scope)
+    }
+
+    // The kind of the var `name` seen from the current scope, if it's been
+    // animated.
+    fn kind(&self, name: &str) -> Option<Kind> {
+        self.scopes().find_map(|s| self.kinds[s].get(name).copied())
+    }
+
+    // Whether `name` is a parameter of the macro being read. Vec3 and colour
+    // ones are only there as their components.
+    fn is_param(&self, name: &str) -> bool {
+        let kind = self.kinds[self.scope].get(name).copied().unwrap_or(Kind::Number);
+        kind.parts(name).iter()
+            .any(|part| self.vars[self.scope].get(part).is_some_and(|v| v.arg.is_some()))
+    }
+
+    // Writes each vec3 or colour var in `rest` out as its components, so
+    // that `POINT pos 5` reads as `POINT pos.x pos.y pos.z 5`. Only whole
+    // words are replaced; inside expressions and strings, use `pos.x` and
+    // so on.
+    fn expand_vars(&self, rest: &str) -> String {
+        let mut out = String::new();
+        let mut at = 0;
+        for word in lexer::words(rest).unwrap_or_default() {
+            // A colour becomes one word, so it can't be mistaken for three
+            // numbers where several colours go; see `next_color`.
+            let parts = match self.kind(word.text) {
+                Some(Kind::Vec3) => Kind::Vec3.parts(word.text).join(" "),
+                Some(Kind::Color) => format!("rgba({})", Kind::Color.parts(word.text).join(", ")),
+                _ => continue,
+            };
+            out.push_str(&rest[at..word.start]);
+            out.push_str(&parts);
+            at = word.end;
+        }
+        out.push_str(&rest[at..]);
+        out
+    }
+
+    // Reads one line, and the rest of any block it opens from `lines`.
+    fn line(&mut self, line: &str, lines: &mut SourceLines) -> Result<(), ErrorKind> {
+        let words = lexer::words(line)?;
+        let (cmd, rest) = match (words.first(), words.get(1)) {
+            (None, _) => return Ok(()),
+            (Some(cmd), None) => (cmd.text, ""),
+            (Some(cmd), Some(arg)) => (cmd.text, &line[arg.start..]),
+        };
+        let expanded;
+        let rest = match &*cmd.to_lowercase() {
+            "animate" | "keyframes" | "extrapolate" => rest,
+            _ => {
+                expanded = self.expand_vars(rest);
+                &expanded
+            },
+        };
+        match &*cmd.to_lowercase() {
+            "point"    => self.commands.push(parse_cmd_point(rest)?),
+            "line"     => self.commands.push(parse_cmd_line(rest)?),
+            "triangle" => self.commands.push(parse_cmd_triangle(rest)?),
+            "mesh" if next_word(rest).0.eq_ignore_ascii_case("begin") => {
+                let mesh = parse_mesh_block(lines, next_word(rest).1)
+                    .map_err(|e| format!("inline mesh: {}", e))?;
+                self.commands.push(Command::Mesh(Lods::new(mesh)));
+            },
+            "mesh"     => self.commands.push(parse_cmd_mesh(rest, &self.dir, obj::load)?),
+            "rawmesh"  => self.commands.push(parse_cmd_mesh(rest, &self.dir, load_rawmesh)?),
+            "ply"      => self.commands.push(parse_cmd_mesh(rest, &self.dir, ply::load)?),
+            "stl"      => self.commands.push(parse_cmd_mesh(rest, &self.dir, stl::load)?),
+            "gltf"     => self.commands.push(parse_cmd_gltf(rest, &self.dir)?),
+            "sphere" | "cube" | "cylinder" | "cone" | "torus" | "plane" =>
+                self.commands.push(parse_cmd_primitive(&cmd.to_lowercase(), rest)?),
+            "meshdef"  => {
+                let (name, rest) = next_word(rest);
+                if name.is_empty() { return Err("MESHDEF needs a name".to_string().into()); }
+                let mesh = parse_mesh_block(lines, rest)
+                    .map_err(|e| format!("mesh \"{}\": {}", name, e))?;
+                if self.meshes[self.scope].insert(name.to_string(), Lods::new(mesh)).is_some() {
+                    return Err(format!("mesh \"{}\" is defined twice", name).into());
+                }
+            },
+            "drawmesh" => {
+                let name = rest.trim();
+                let lods = self.scopes().find_map(|s| self.meshes[s].get(name))
+                    .ok_or_else(|| format!("mesh \"{}\" is not defined", name))?
+                    .clone();
+                self.commands.push(Command::Mesh(lods));
+            },
+            "text"     => self.commands.push(parse_cmd_text(rest, &self.dir)?),
+            "sprite"   => self.commands.push(parse_cmd_sprite(rest, &self.dir)?),
+
+            "identity"  => self.commands.push(Command::Identity),
+            "push"      => {
+                self.pushes.push((lines.number, lines.last.clone()));
+                self.commands.push(Command::Push);
+            },
+            "pop"       => {
+                if self.pushes.pop().is_none() {
+                    return Err("POP without a PUSH before it".to_string().into());
+                }
+                self.commands.push(Command::Pop);
+            },
+            "translate" => self.commands.push(parse_cmd_translate(rest)?),
+            "scale"     => self.commands.push(parse_cmd_scale(rest)?),
+            "rotate"    => self.commands.push(parse_cmd_rotate(rest)?),
+
+            "color"     => self.commands.push(parse_cmd_color(rest)?),
+            "gradient"  => self.commands.push(parse_cmd_gradient(rest)?),
+            "background" => self.background = parse_cmd_background(rest, &self.dir)?,
+            "begin"     => self.begin(rest, lines)?,
+            "define"    => self.define(rest, lines)?,
+            "use"       => self.use_macro(rest, lines)?,
+            "include"   => {
+                let (before, path, after) = split_quoted(rest)?;
+                if !before.trim().is_empty() || !after.trim().is_empty() {
+                    return Err("expected INCLUDE \"path\"".to_string().into());
+                }
+                self.include(&path, &resolve(&self.dir, &path))?;
+            },
+            "animate" | "keyframes" => {
+                let (var, kind, animations) = if cmd.eq_ignore_ascii_case("animate") {
+                    parse_cmd_animate(rest)?
+                } else {
+                    let (var, animation) = parse_keyframes_block(lines, rest)?;
+                    (var.clone(), Kind::Number, vec![animation])
+                };
+                if self.is_param(&var) {
+                    return Err(format!("\"{}\" is a parameter and can't be animated", var).into());
+                }
+                let known = *self.kinds[self.scope].entry(var.clone()).or_insert(kind);
+                if known != kind {
+                    return Err(format!(
+                        "var \"{}\" is a {}, not a {}", var, known.name(), kind.name()).into());
+                }
+                let vars = &mut self.vars[self.scope];
+                if kind != Kind::Number && vars.contains_key(&var) {
+                    return Err(format!(
+                        "EXTRAPOLATE for {} var \"{}\" must come after its ANIMATE", kind.name(), var).into());
+                }
+                for (part, animation) in kind.parts(&var).into_iter().zip(animations) {
+                    add_animation(&mut vars.entry(part).or_default().anims, animation)
+                        .map_err(|e| format!("animation for var \"{}\" {}", var, e))?;
                 }
-                anims.push(animation);
             }
+            "extrapolate" => {
+                let (name, rest) = next_word(rest);
+                if name.is_empty() { return Err("EXTRAPOLATE needs a var name".to_string().into()); }
+                let kind = self.kinds[self.scope].get(name).copied().unwrap_or(Kind::Number);
+                if self.is_param(name) {
+                    return Err(format!("\"{}\" is a parameter and can't be extrapolated", name).into());
+                }
+                for part in kind.parts(name) {
+                    parse_cmd_extrapolate(rest, self.vars[self.scope].entry(part).or_default())
+                        .map_err(|e| format!("var \"{}\": {}", name, e))?;
+                }
+            },
 
-            _ => return Err(format!("line \"{}\" does not have a command", line)),
+            _ => return Err(ErrorKind::UnknownCommand(cmd.to_string())),
         };
+        Ok(())
+    }
+}
+
+// The file's lines with comments taken out and continued lines joined up,
+// counted as they're read so errors can say where they are. Macros replay
+// lines read earlier.
+struct SourceLines {
+    source: Source,
+    // The name of the file the lines are from.
+    file: String,
+    // The last line read, and the number, from 1, of the first line it was
+    // joined from.
+    last: String,
+    number: usize,
+    read: usize,
+}
+
+enum Source {
+    File(io::Lines<io::BufReader<File>>),
+    Stored(std::vec::IntoIter<(usize, String)>),
+}
+
+impl SourceLines {
+    fn open(path: &str) -> io::Result<SourceLines> {
+        Ok(SourceLines {
+            source: Source::File(read_lines(path)?),
+            file: path.to_string(), last: String::new(), number: 0, read: 0,
+        })
     }
 
-    Ok(Scene {
-        commands: commands,
-        vars: vars,
-    })
+    // Lines already read from `file`, each with its number.
+    fn stored(file: &str, lines: Vec<(usize, String)>) -> SourceLines {
+        SourceLines {
+            source: Source::Stored(lines.into_iter()),
+            file: file.to_string(), last: String::new(), number: 0, read: 0,
+        }
+    }
+}
+
+impl Iterator for SourceLines {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        let lines = match &mut self.source {
+            Source::File(lines) => lines,
+            Source::Stored(lines) => {
+                let (number, line) = lines.next()?;
+                self.number = number;
+                self.last = line.clone();
+                return Some(Ok(line));
+            },
+        };
+        self.number = self.read + 1;
+        let mut line = String::new();
+        loop {
+            let next = match lines.next()? {
+                Ok(l) => l,
+                Err(e) => return Some(Err(e)),
+            };
+            self.read += 1;
+            let next = lexer::strip_comment(&next);
+            let next = if line.is_empty() { next } else { next.trim_start() };
+            match lexer::continued(next) {
+                Some(start) => {
+                    line.push_str(start);
+                    line.push(' ');
+                },
+                None => {
+                    line.push_str(next);
+                    break;
+                },
+            }
+        }
+        self.last = line.clone();
+        Some(Ok(line))
+    }
+}
+
+// An error about `line`, pointing at what its message is about: the text an
+// expression error's caret is under, or else the first thing it quotes, or
+// else everything from byte `from` on. Expression errors lose their own copy
+// of the text and caret, since the whole line gets shown.
+fn locate(kind: ErrorKind, file: &str, number: usize, line: &str, from: usize) -> ParseError {
+    let from = line.len() - line[from..].trim_start().len();
+    let msg = match &kind {
+        ErrorKind::Invalid(msg) => msg.clone(),
+        _ => return ParseError::at(kind, file, number, line, from, line.len()),
+    };
+    let parts: Vec<&str> = msg.split('\n').collect();
+    let caret = (1..parts.len())
+        .find(|&i| !parts[i].trim().is_empty() && parts[i].trim().chars().all(|c| c == '^'));
+    if let Some(i) = caret {
+        let src = parts[i - 1].trim_start();
+        let indent = parts[i - 1].len() - src.len();
+        let msg = [&parts[..i - 1], &parts[i + 1..]].concat().join("\n");
+        let kind = ErrorKind::Invalid(msg);
+        let col = parts[i].len() - parts[i].trim_start().len() - indent;
+        let len = parts[i].trim().len();
+        // Errors from splitting the line quote all of it.
+        let found = line[from..].find(src).map(|at| from + at).or_else(|| line.find(src));
+        return match found {
+            Some(at) => {
+                // The caret counts characters; the span wants bytes.
+                let byte = |n: usize| src.char_indices().nth(n).map_or(src.len(), |(b, _)| b);
+                ParseError::at(kind, file, number, line, at + byte(col), at + byte(col + len))
+            },
+            None => ParseError::at(kind, file, number, line, from, line.len()),
+        };
+    }
+    let quoted = msg.split('"').skip(1).step_by(2).filter(|q| !q.is_empty())
+        .find_map(|q| line[from..].find(q).map(|at| (from + at, from + at + q.len())));
+    match quoted {
+        Some((start, end)) => ParseError::at(kind, file, number, line, start, end),
+        None => ParseError::at(kind, file, number, line, from, line.len()),
+    }
+}
+
+// The lines of a block, up to the END that closes it. Blocks inside have
+// ENDs of their own.
+fn block_lines(cmd_name: &str, lines: &mut SourceLines) -> Result<Vec<(usize, String)>, String> {
+    let mut body = vec![];
+    let mut depth = 0;
+    loop {
+        let line = lines.next().ok_or_else(|| ran_out_of_lines(cmd_name))?
+            .map_err(|e| e.to_string())?;
+        if line.trim().eq_ignore_ascii_case("end") {
+            if depth == 0 { return Ok(body); }
+            depth -= 1;
+        } else if opens_block(&line) {
+            depth += 1;
+        }
+        body.push((lines.number, line));
+    }
+}
+
+// Whether `line` starts a block that's closed by END.
+fn opens_block(line: &str) -> bool {
+    let (cmd, rest) = next_word(line);
+    match cmd.to_lowercase().as_str() {
+        "begin" | "define" | "keyframes" | "meshdef" => true,
+        "mesh" => next_word(rest).0.eq_ignore_ascii_case("begin"),
+        _ => false,
+    }
+}
+
+// Splits `name(a, b)` into the name and what's between the commas. A name
+// on its own has no arguments.
+fn parse_call(s: &str) -> Result<(&str, Vec<&str>), String> {
+    let (name, args) = match s.find('(') {
+        None => (s, ""),
+        Some(i) => match s[i + 1..].strip_suffix(')') {
+            Some(args) => (&s[..i], args),
+            None => return Err(format!("expected \"name(args)\", found \"{}\"", s)),
+        },
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("bad macro name \"{}\"", name));
+    }
+    Ok((name, split_args(args)))
+}
+
+// What's between the commas of `args`. Commas inside an argument's own
+// parentheses don't count.
+fn split_args(args: &str) -> Vec<&str> {
+    if args.trim().is_empty() { return vec![]; }
+    let mut parts = vec![];
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(args[start..i].trim());
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    parts.push(args[start..].trim());
+    parts
+}
+
+// The scope `scope` and the ones around it, innermost first.
+fn scope_chain(parents: &[Option<usize>], scope: usize) -> impl Iterator<Item = usize> + '_ {
+    std::iter::successors(Some(scope), move |&s| parents[s])
+}
+
+// `path` as written in a file in `dir`, which is relative to that folder.
+fn resolve(dir: &Path, path: &str) -> String {
+    dir.join(path).to_string_lossy().into_owned()
+}
+
+// Puts `animation` into `anims`, keeping them in time order.
+fn add_animation(anims: &mut Vec<Animation>, animation: Animation) -> Result<(), String> {
+    for i in 0..anims.len() {
+        let other = &anims[i];
+        if animation.t2 < other.t1 {
+            anims.insert(i, animation);
+            return Ok(());
+        }
+        if animation.overlaps(other) {
+            return Err("overlaps with another".to_string());
+        }
+    }
+    anims.push(animation);
+    Ok(())
 }
 
 fn ran_out_of_lines(cmd_name: &str) -> String {
@@ -210,9 +1101,82 @@ fn parse_cmd_triangle(rest: &str) -> Result<Command, String> {
     ))
 }
 
-fn parse_cmd_mesh(rest: &str) -> Result<Command, String> {
-    let path = rest.trim_matches('"');
-    if rest.len() - path.len() != 2 { return Err("expected \" enclosed filepath".to_string()); }
+// MESH|PLY|STL|RAWMESH "path" [mesh ops]
+fn parse_cmd_mesh(
+    rest: &str,
+    dir: &Path,
+    load: fn(&str) -> Result<Mesh, String>
+) -> Result<Command, String> {
+    let (path, ops) = parse_mesh_path(rest, dir)?;
+    let mut mesh = load(&path)?;
+    meshops::apply(&mut mesh, &ops).map_err(|e| format!("mesh \"{}\": {}", path, e))?;
+    Ok(Command::Mesh(Lods::new(mesh)))
+}
+
+// GLTF "path" [mesh ops]
+fn parse_cmd_gltf(rest: &str, dir: &Path) -> Result<Command, String> {
+    let (path, ops) = parse_mesh_path(rest, dir)?;
+    let mut model = gltf::load(&path)?;
+    for mesh in model.meshes.iter_mut() {
+        meshops::apply(mesh, &ops).map_err(|e| format!("model \"{}\": {}", path, e))?;
+    }
+    Ok(Command::Model(model))
+}
+
+fn parse_mesh_path(rest: &str, dir: &Path) -> Result<(String, Vec<MeshOp>), String> {
+    let (before, path, after) = split_quoted(rest)
+        .map_err(|_| "expected \" enclosed filepath".to_string())?;
+    if !before.trim().is_empty() { return Err("expected \" enclosed filepath".to_string()); }
+    Ok((resolve(dir, &path), parse_mesh_ops(after)?))
+}
+
+// [NORMALS smooth [crease_degrees] | NORMALS flat] [WELD distance] [SUBDIVIDE levels]
+// in any order, any number of times.
+fn parse_mesh_ops(rest: &str) -> Result<Vec<MeshOp>, String> {
+    let mut ops = vec![];
+    let all = lexer::words(rest)?;
+    let mut words = all.iter().peekable();
+    let number = |word: Option<&lexer::Word>, what: &str| -> Result<f32, String> {
+        let word = word.ok_or_else(|| format!("expected {}", what))?;
+        parse_word(rest, word)
+    };
+    while let Some(word) = words.next() {
+        let op = match word.text.to_lowercase().as_str() {
+            "normals" => match words.next().map(|w| w.text.to_lowercase()).as_deref() {
+                Some("flat") => MeshOp::Normals { smooth: false, crease: 0.0 },
+                Some("smooth") => {
+                    let crease = match words.peek().and_then(|w| w.text.parse().ok()) {
+                        Some(angle) => { words.next(); angle },
+                        None => 180.0,
+                    };
+                    MeshOp::Normals { smooth: true, crease }
+                },
+                _ => return Err("expected NORMALS smooth or NORMALS flat".to_string()),
+            },
+            "weld" => {
+                let eps = number(words.next(), "a weld distance")?;
+                if eps < 0.0 { return Err("weld distance must not be negative".to_string()); }
+                MeshOp::Weld(eps)
+            },
+            "subdivide" => {
+                let levels = number(words.next(), "a number of levels")?;
+                if levels < 0.0 || levels.fract() != 0.0 {
+                    return Err("subdivision levels must be a whole number".to_string());
+                }
+                // Every level quadruples the triangles.
+                if levels > 6.0 { return Err("at most 6 subdivision levels".to_string()); }
+                MeshOp::Subdivide(levels as usize)
+            },
+            _ => return Err(format!("unknown mesh option \"{}\"", word.text)),
+        };
+        ops.push(op);
+    }
+    Ok(ops)
+}
+
+// Reads our original mesh format: a "points" line, one "x y z" line per
+// point, then a "triangles" line and one "i j k" line per triangle.
+fn load_rawmesh(path: &str) -> Result<Mesh, String> {
     let obj_lines = &mut read_lines(path)
         .map_err(|_| { format!("file \"{}\" does not exist", path) })?;
 
@@ -230,15 +1194,249 @@ fn parse_cmd_mesh(rest: &str) -> Result<Command, String> {
     loop {
         match next(obj_lines) {
             Ok(line) => {
-                let xs = parse_n_u8s(3, line.trim())?;
-                triangles.push(xs[0] as usize);
-                triangles.push(xs[1] as usize);
-                triangles.push(xs[2] as usize);
+                let xs = parse_n_u32s(3, line.trim())?;
+                triangles.push([xs[0], xs[1], xs[2]]);
             },
             _ => break
         }
     }
-    Ok(Command::Mesh { points: points, triangles: triangles })
+    let mut mesh = Mesh { points, triangles, ..Mesh::new() };
+    mesh.validate().map_err(|e| format!("bad mesh file \"{}\": {}", path, e))?;
+    Ok(mesh)
+}
+
+// MESH BEGIN [mesh ops]
+// MESHDEF name [mesh ops]
+//
+// Both are followed by a mesh written out in sections, each a keyword line
+// and then one line per item, and closed by END. POINTS and TRIANGLES are
+// needed; the others, if given, need one line per point.
+//
+//   POINTS     x y z
+//   TRIANGLES  i j k
+//   NORMALS    x y z
+//   UVS        u v
+//   COLORS     r g b
+fn parse_mesh_block(
+    lines: &mut SourceLines,
+    rest: &str,
+) -> Result<Mesh, String> {
+    let ops = parse_mesh_ops(rest)?;
+    let mut mesh = Mesh::new();
+    let (mut normals, mut uvs, mut colors) = (vec![], vec![], vec![]);
+    let mut section = String::new();
+    loop {
+        let line = lines.next().ok_or_else(|| ran_out_of_lines("mesh"))?
+            .map_err(|e| e.to_string())?;
+        let line = line.trim();
+        if line.is_empty() { continue; }
+        let word = line.to_lowercase();
+        match word.as_str() {
+            "end" => break,
+            "points" | "triangles" | "normals" | "uvs" | "colors" => section = word,
+            _ => match section.as_str() {
+                "points" => {
+                    let fs = parse_n_floats(3, line)?;
+                    mesh.points.push(Point3 { x: fs[0], y: fs[1], z: fs[2] });
+                },
+                "triangles" => {
+                    let xs = parse_n_u32s(3, line)?;
+                    mesh.triangles.push([xs[0], xs[1], xs[2]]);
+                },
+                "normals" => {
+                    let fs = parse_n_floats(3, line)?;
+                    normals.push(Point3 { x: fs[0], y: fs[1], z: fs[2] });
+                },
+                "uvs" => {
+                    let fs = parse_n_floats(2, line)?;
+                    uvs.push(Point2 { x: fs[0], y: fs[1] });
+                },
+                "colors" => {
+                    let cs = parse_n_u8s(3, line)?;
+                    colors.push(Color { r: cs[0], g: cs[1], b: cs[2] });
+                },
+                _ => return Err(format!(
+                    "expected POINTS, TRIANGLES, NORMALS, UVS or COLORS before \"{}\"", line)),
+            },
+        }
+    }
+
+    if !normals.is_empty() { mesh.normals = Some(normals); }
+    if !uvs.is_empty() { mesh.uvs = Some(uvs); }
+    if !colors.is_empty() { mesh.colors = Some(colors); }
+    // Checked before the ops, which trust the indices.
+    mesh.validate()?;
+    meshops::apply(&mut mesh, &ops)?;
+    Ok(mesh)
+}
+
+// SPHERE [segments rings]
+// CUBE [segments]
+// CYLINDER [segments rings]
+// CONE [segments rings]
+// TORUS [minor_radius [segments rings]]
+// PLANE [segments rings]
+// each followed by optional mesh ops.
+fn parse_cmd_primitive(kind: &str, rest: &str) -> Result<Command, String> {
+    // The counts end at the first word that isn't a number.
+    let words = lexer::words(rest)?;
+    let counts = words.iter().take_while(|w| w.text.parse::<f32>().is_ok()).count();
+    let split = words.get(counts).map_or(rest.len(), |w| w.start);
+    let ops = parse_mesh_ops(&rest[split..])?;
+    let mut xs = parse_n_floats(counts, &rest[..split])?;
+
+    let minor = if kind == "torus" && !xs.is_empty() { xs.remove(0) } else { 0.25 };
+    let (segments, rings) = match kind {
+        "cube"   => (4, 4),
+        "plane"  => (1, 1),
+        "sphere" => (24, 12),
+        "torus"  => (32, 12),
+        _        => (24, 1),
+    };
+    let n = if kind == "cube" { 1 } else { 2 };
+    let counts = match xs.len() {
+        0 => vec![segments, rings],
+        len if len == n => xs.iter().map(|&x| x as usize).collect(),
+        len => return Err(format!(
+            "expected {} tessellation counts for {}, found {}", n, kind, len)),
+    };
+    if xs.iter().any(|&x| x < 1.0 || x.fract() != 0.0) {
+        return Err("tessellation counts must be positive integers".to_string());
+    }
+    // A sphere of 256 by 256 is already 130,000 triangles.
+    if xs.iter().any(|&x| x > 256.0) {
+        return Err("at most 256 segments or rings".to_string());
+    }
+    let (segments, rings) = (counts[0], counts[counts.len() - 1]);
+
+    let mut mesh = match kind {
+        "sphere"   => primitives::sphere(segments, rings),
+        "cube"     => primitives::cube(segments),
+        "cylinder" => primitives::cylinder(segments, rings),
+        "cone"     => primitives::cone(segments, rings),
+        "torus"    => primitives::torus(minor, segments, rings),
+        _          => primitives::plane(segments, rings),
+    };
+    meshops::apply(&mut mesh, &ops)?;
+    Ok(Command::Mesh(Lods::new(mesh)))
+}
+
+// TEXT x y z "string" size [ALIGN h] [VALIGN v] [FONT "path.bdf"] [SCREEN]
+fn parse_cmd_text(rest: &str, dir: &Path) -> Result<Command, String> {
+    let (pos, string, rest) = split_quoted(rest)?;
+    let xs = parse_n_vals(3, pos.trim())?;
+    let (size, mut rest) = next_word(rest);
+    let size = parse_n_vals(1, size)?.remove(0);
+
+    let mut align = Align::DEFAULT;
+    let mut projected = true;
+    let mut font = None;
+    loop {
+        let (opt, after) = next_word(rest);
+        rest = after;
+        match &*opt.to_lowercase() {
+            "" => break,
+            "screen" => projected = false,
+            "align" => {
+                let (arg, after) = next_word(rest);
+                rest = after;
+                align.h = match &*arg.to_lowercase() {
+                    "left"   => HAlign::Left,
+                    "center" => HAlign::Center,
+                    "right"  => HAlign::Right,
+                    _ => return Err(format!("unknown horizontal alignment \"{}\"", arg)),
+                };
+            },
+            "valign" => {
+                let (arg, after) = next_word(rest);
+                rest = after;
+                align.v = match &*arg.to_lowercase() {
+                    "top"      => VAlign::Top,
+                    "middle"   => VAlign::Middle,
+                    "baseline" => VAlign::Baseline,
+                    "bottom"   => VAlign::Bottom,
+                    _ => return Err(format!("unknown vertical alignment \"{}\"", arg)),
+                };
+            },
+            "font" => {
+                let (before, path, after) = split_quoted(rest)?;
+                if !before.trim().is_empty() {
+                    return Err("expected \" enclosed font path".to_string());
+                }
+                rest = after;
+                font = Some(Font::load_bdf(&resolve(dir, &path))?);
+            },
+            _ => return Err(format!("unknown TEXT option \"{}\"", opt)),
+        }
+    }
+
+    Ok(Command::Text {
+        p: ValPoint3 { x: xs[0].clone(), y: xs[1].clone(), z: xs[2].clone() },
+        text: text::parse_template(&string)?,
+        size,
+        align,
+        projected,
+        font: font.unwrap_or_else(Font::builtin),
+    })
+}
+
+// SPRITE "path" x y z [SCALE s] [ROTATE theta] [ALPHA a]
+//     [FILTER nearest|bilinear] [BILLBOARD] [SHEET cols rows] [FRAME f]
+fn parse_cmd_sprite(rest: &str, dir: &Path) -> Result<Command, String> {
+    let (before, path, rest) = split_quoted(rest)?;
+    if !before.trim().is_empty() { return Err("expected \" enclosed filepath".to_string()); }
+    let (pos, mut rest) = split_words(3, rest);
+    let xs = parse_n_vals(3, pos)?;
+
+    let mut sprite = Sprite {
+        image: Image::load(&resolve(dir, &path))?,
+        p: ValPoint3 { x: xs[0].clone(), y: xs[1].clone(), z: xs[2].clone() },
+        scale: Val::Raw(1.0),
+        rotate: Val::Raw(0.0),
+        alpha: Val::Raw(1.0),
+        filter: Filter::Nearest,
+        billboard: false,
+        sheet: (1, 1),
+        frame: Val::Raw(0.0),
+    };
+    loop {
+        let (opt, after) = next_word(rest);
+        rest = after;
+        let arg = |rest: &mut &str| -> Result<Val, String> {
+            let (arg, after) = next_word(rest);
+            *rest = after;
+            Ok(parse_n_vals(1, arg)?.remove(0))
+        };
+        match &*opt.to_lowercase() {
+            "" => break,
+            "scale"  => sprite.scale = arg(&mut rest)?,
+            "rotate" => sprite.rotate = arg(&mut rest)?,
+            "alpha"  => sprite.alpha = arg(&mut rest)?,
+            "frame"  => sprite.frame = arg(&mut rest)?,
+            "billboard" => sprite.billboard = true,
+            "filter" => {
+                let (arg, after) = next_word(rest);
+                rest = after;
+                sprite.filter = match &*arg.to_lowercase() {
+                    "nearest"  => Filter::Nearest,
+                    "bilinear" => Filter::Bilinear,
+                    _ => return Err(format!("unknown filter \"{}\"", arg)),
+                };
+            },
+            "sheet" => {
+                let (args, after) = split_words(2, rest);
+                rest = after;
+                let xs = parse_n_u8s(2, args)?;
+                let (cols, rows) = (xs[0] as usize, xs[1] as usize);
+                if cols == 0 || rows == 0 || cols > sprite.image.w || rows > sprite.image.h {
+                    return Err(format!("can't split image into {}x{} cells", cols, rows));
+                }
+                sprite.sheet = (cols, rows);
+            },
+            _ => return Err(format!("unknown SPRITE option \"{}\"", opt)),
+        }
+    }
+    Ok(Command::Sprite(sprite))
 }
 
 fn parse_cmd_translate(rest: &str) -> Result<Command, String> {
@@ -259,63 +1457,434 @@ fn parse_cmd_rotate(rest: &str) -> Result<Command, String> {
     })
 }
 
+// COLOR r g b [a]
+// COLOR colour
 fn parse_cmd_color(rest: &str) -> Result<Command, String> {
-    let xs = parse_n_u8s(3, rest)?;
-    Ok(Command::Color(Color { r: xs[0], g: xs[1], b: xs[2] }))
+    Ok(Command::Color(parse_color(rest)?))
 }
 
-fn parse_cmd_animate(rest: &str) -> Result<(String, Animation), String> {
-    let (var, rest) = rest.split_once(" ")
-        .ok_or("line does not have a first thing")?;
-    let xs = parse_n_floats(4, rest)?;
-    Ok((
-        var.to_string(),
-        Animation { from: xs[0], to: xs[1], t1: xs[2], t2: xs[3]}))
+// One colour: a colour word, or r g b [a]. Channels, alpha included, run
+// from 0 to 255.
+fn parse_color(rest: &str) -> Result<ValColor, String> {
+    let words = lexer::words(rest)?;
+    if words.len() == 1 {
+        if let Some(c) = parse_color_word(words[0].text)? { return Ok(c); }
+    }
+    if words.len() != 3 && words.len() != 4 {
+        return Err(format!("expected 3 or 4 values, found {}", words.len()));
+    }
+    Ok(ValColor::new(&parse_n_vals(words.len(), rest)?))
 }
 
-fn parse_n_u8s(
-    n: usize,
-    line: &str,
-) -> Result<Vec<u8>, String> {
-    let xs: Vec<u8> = line.split(" ")
-        .map(|s| s.parse())
-        .collect::<Result<Vec<u8>, _>>()
-        .map_err(|e| e.to_string())?;
-    if xs.len() == n {
-        return Ok(xs);
+// The colour at the start of `words`, where several colours may follow one
+// another, and how many words it took. So that they can be told apart, a
+// colour given as numbers is only r g b; an alpha needs a colour word.
+fn next_color(words: &[lexer::Word]) -> Result<(ValColor, usize), String> {
+    if let Some(c) = parse_color_word(words.first().map_or("", |w| w.text))? {
+        return Ok((c, 1));
     }
-    Err(format!("expected {} u8s, found {}", n, xs.len()))
+    if words.len() < 3 {
+        let found: Vec<&str> = words.iter().map(|w| w.text).collect();
+        return Err(format!("expected a color, found \"{}\"", found.join(" ")));
+    }
+    let xs = words[..3].iter().map(|w| parse_val(w.text)).collect::<Result<Vec<Val>, _>>()?;
+    Ok((ValColor::new(&xs), 3))
 }
 
-fn parse_n_floats(
-    n: usize,
-    line: &str,
-) -> Result<Vec<f32>, String> {
-    let xs: Vec<f32> = line.split(" ")
-        .filter(|s| !s.is_empty())
-        .map(|s| s.parse().map_err(|e| format!("parsing \"{}\": {}", s, e)))
-        .collect::<Result<Vec<f32>, _>>()
-        .map_err(|e| e.to_string())?;
+// rgb(r, g, b), rgba(r, g, b, a), #rrggbb or #rrggbbaa, or None if `word`
+// isn't written as a colour. The channels of rgb() and rgba() can be any
+// values.
+fn parse_color_word(word: &str) -> Result<Option<ValColor>, String> {
+    let lower = word.to_lowercase();
+    let (n, inner) = match (lower.strip_prefix("rgb("), lower.strip_prefix("rgba(")) {
+        (Some(inner), _) => (3, inner),
+        (_, Some(inner)) => (4, inner),
+        _ if word.starts_with('#') => {
+            let c = parse_hex_color(word)?;
+            let raw = |x: u8| Val::Raw(x as f32);
+            return Ok(Some(ValColor::new(&[raw(c[0]), raw(c[1]), raw(c[2]), raw(c[3])])));
+        },
+        _ => return Ok(None),
+    };
+    // The original case, for var names.
+    let inner = &word[word.len() - inner.len()..];
+    let args = inner.strip_suffix(')').ok_or_else(|| format!("bad color \"{}\"", word))?;
+    let xs = split_args(args).into_iter().map(parse_val).collect::<Result<Vec<Val>, _>>()?;
+    if xs.len() != n {
+        return Err(format!("expected {} values in \"{}\", found {}", n, word, xs.len()));
+    }
+    Ok(Some(ValColor::new(&xs)))
+}
+
+// #rrggbb or #rrggbbaa, as r, g, b and a.
+fn parse_hex_color(s: &str) -> Result<[u8; 4], String> {
+    let hex = &s[1..];
+    let channel = |i: usize| hex.get(i..i + 2).and_then(|h| u8::from_str_radix(h, 16).ok());
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b, 255]),
+        (8, Some(r), Some(g), Some(b)) => match channel(6) {
+            Some(a) => Ok([r, g, b, a]),
+            None => Err(format!("bad color \"{}\"", s)),
+        },
+        _ => Err(format!("bad color \"{}\"", s)),
+    }
+}
+
+// GRADIENT linear x1 y1 x2 y2 [spread] stops...
+// GRADIENT radial cx cy r [spread] stops...
+// GRADIENT conic cx cy angle [spread] stops...
+// where spread is pad (the default), repeat or reflect, and each stop is
+// `offset r g b` or `offset colour`.
+fn parse_cmd_gradient(rest: &str) -> Result<Command, String> {
+    let (kind, rest) = next_word(rest);
+    let n = match &*kind.to_lowercase() {
+        "linear" => 4,
+        "radial" | "conic" => 3,
+        _ => return Err(format!("unknown gradient \"{}\"", kind)),
+    };
+    let (args, rest) = split_words(n, rest);
+    let xs = parse_n_vals(n, args)?;
+    let zero = || Val::Raw(0.0);
+    let shape = match &*kind.to_lowercase() {
+        "linear" => ValShape::Linear {
+            from: ValPoint3 { x: xs[0].clone(), y: xs[1].clone(), z: zero() },
+            to:   ValPoint3 { x: xs[2].clone(), y: xs[3].clone(), z: zero() },
+        },
+        "radial" => ValShape::Radial {
+            center: ValPoint3 { x: xs[0].clone(), y: xs[1].clone(), z: zero() },
+            radius: xs[2].clone(),
+        },
+        _ => ValShape::Conic {
+            center: ValPoint3 { x: xs[0].clone(), y: xs[1].clone(), z: zero() },
+            angle: xs[2].clone(),
+        },
+    };
+
+    let (word, after) = next_word(rest);
+    let (spread, rest) = match &*word.to_lowercase() {
+        "pad"     => (Spread::Pad, after),
+        "repeat"  => (Spread::Repeat, after),
+        "reflect" => (Spread::Reflect, after),
+        _ => (Spread::Pad, rest),
+    };
+
+    let words = lexer::words(rest)?;
+    let mut stops = vec![];
+    let mut at = 0;
+    while at < words.len() {
+        let offset = parse_n_floats(1, words[at].text)?[0];
+        let (color, n) = next_color(&words[at + 1..])?;
+        stops.push(ValStop { offset, color });
+        at += 1 + n;
+    }
+    if stops.len() < 2 {
+        return Err(format!("expected at least 2 stops of \"offset r g b\", found {}", stops.len()));
+    }
+    stops.sort_by(|a, b| a.offset.total_cmp(&b.offset));
+
+    Ok(Command::Gradient { shape, spread, stops })
+}
+
+// BACKGROUND r g b [a]
+// BACKGROUND colour
+// BACKGROUND vertical|horizontal r1 g1 b1 r2 g2 b2
+//
+// Either colour of a gradient can also be a colour word. A see-through
+// background fades the last frame rather than hiding it.
+// BACKGROUND image "path" [stretch|tile|center]
+// BACKGROUND none
+fn parse_cmd_background(rest: &str, dir: &Path) -> Result<Background, String> {
+    let (kind, args) = next_word(rest);
+    match &*kind.to_lowercase() {
+        "none" => Ok(Background::None),
+        "vertical" | "horizontal" => {
+            let words = lexer::words(args)?;
+            let (from, n) = next_color(&words)?;
+            let (to, m) = next_color(&words[n..])?;
+            if n + m < words.len() {
+                return Err(format!("unexpected \"{}\" after the colors", words[n + m].text));
+            }
+            Ok(Background::Gradient { vertical: kind.eq_ignore_ascii_case("vertical"), from, to })
+        },
+        "image" => {
+            let (before, path, after) = split_quoted(args)?;
+            if !before.trim().is_empty() {
+                return Err("expected \" enclosed filepath".to_string());
+            }
+            let fit = match &*after.trim().to_lowercase() {
+                "" | "stretch" => Fit::Stretch,
+                "tile"   => Fit::Tile,
+                "center" => Fit::Center,
+                other => return Err(format!("unknown image fit \"{}\"", other)),
+            };
+            Ok(Background::Image { image: Image::load(&resolve(dir, &path))?, fit })
+        },
+        _ => Ok(Background::Solid(parse_color(rest.trim())?)),
+    }
+}
+
+// ANIMATE var from to t1 t2 [easing] [RGB|LINEAR-RGB|HSV]
+//
+// `from` and `to` are both numbers, both vec3s written (x, y, z), or both
+// colours written rgb(r, g, b), rgba(r, g, b, a), #rrggbb or #rrggbbaa. A
+// vec3 or colour var can be used wherever a point or colour goes, and its
+// components in expressions as `var.x`, `var.y`, `var.z` or `var.r`,
+// `var.g`, `var.b`, `var.a`. The last option picks how colours are blended,
+// RGB by default; alpha always goes straight from one value to the other.
+fn parse_cmd_animate(rest: &str) -> Result<(String, Kind, Vec<Animation>), String> {
+    let (var, rest) = next_word(rest);
+    if var.is_empty() { return Err("ANIMATE needs a var name".to_string()); }
+    let (values, rest) = split_words(2, rest);
+    let (times, options) = split_words(2, rest);
+    let (from, to) = match next_word(values) {
+        (from, to) if !to.is_empty() => (parse_value(from)?, parse_value(to.trim())?),
+        _ => return Err(format!("expected a from and a to value, found \"{}\"", values)),
+    };
+    let ts = parse_n_floats(2, times)?;
+    let (t1, t2) = (ts[0], ts[1]);
+
+    let mut ease = Easing::Linear;
+    let mut space = None;
+    let mut options = options;
+    while !options.is_empty() {
+        let (word, after) = next_word(options);
+        options = after;
+        match word.to_lowercase().as_str() {
+            "rgb" => space = Some(ColorSpace::Rgb),
+            "linear-rgb" => space = Some(ColorSpace::Linear),
+            "hsv" => space = Some(ColorSpace::Hsv),
+            _ => ease = Easing::parse(word)?,
+        }
+    }
+    let animation = |motion| Animation { t1, t2, motion };
+
+    let (kind, animations) = match (from, to) {
+        (Value::Number(from), Value::Number(to)) =>
+            (Kind::Number, vec![animation(Motion::Tween { from, to, ease })]),
+        (Value::Vec3(from), Value::Vec3(to)) => (Kind::Vec3, (0..3)
+            .map(|i| animation(Motion::Tween { from: from[i], to: to[i], ease }))
+            .collect()),
+        (Value::Color(from, from_alpha), Value::Color(to, to_alpha)) => {
+            let space = space.unwrap_or(ColorSpace::Rgb);
+            let mut animations: Vec<Animation> = (0..3)
+                .map(|channel| animation(Motion::Color { from, to, space, channel, ease }))
+                .collect();
+            animations.push(animation(Motion::Tween { from: from_alpha, to: to_alpha, ease }));
+            (Kind::Color, animations)
+        },
+        _ => return Err("from and to must be the same kind of value".to_string()),
+    };
+    if space.is_some() && kind != Kind::Color {
+        return Err(format!("a color space only applies to colors, not a {}", kind.name()));
+    }
+    Ok((var.to_string(), kind, animations))
+}
+
+// A value ANIMATE can go from or to.
+enum Value {
+    Number(f32),
+    Vec3([f32; 3]),
+    // With its alpha, from 0 to 255.
+    Color(Color, f32),
+}
+
+// 5, (1, 2, 3), rgb(255, 128, 0), rgba(255, 128, 0, 128), #ff8000 or
+// #ff800080
+fn parse_value(s: &str) -> Result<Value, String> {
+    // The `n` comma-separated values between the parentheses, as words.
+    let list = |n: usize, inner: &str| -> Result<String, String> {
+        let xs: Vec<&str> = inner.split(',').map(str::trim).collect();
+        if xs.len() != n {
+            return Err(format!("expected {} values in \"{}\", found {}", n, s, xs.len()));
+        }
+        Ok(xs.join(" "))
+    };
+    let lower = s.to_lowercase();
+    let rgb = |c: &[u8]| Value::Color(Color { r: c[0], g: c[1], b: c[2] }, *c.get(3).unwrap_or(&255) as f32);
+    if let Some(inner) = s.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+        let xs = parse_n_floats(3, &list(3, inner)?)?;
+        Ok(Value::Vec3([xs[0], xs[1], xs[2]]))
+    } else if let Some(inner) = lower.strip_prefix("rgb(").and_then(|s| s.strip_suffix(')')) {
+        Ok(rgb(&parse_n_u8s(3, &list(3, inner)?)?))
+    } else if let Some(inner) = lower.strip_prefix("rgba(").and_then(|s| s.strip_suffix(')')) {
+        Ok(rgb(&parse_n_u8s(4, &list(4, inner)?)?))
+    } else if s.starts_with('#') {
+        Ok(rgb(&parse_hex_color(s)?))
+    } else {
+        Ok(Value::Number(parse_n_floats(1, s)?[0]))
+    }
+}
+
+// EXTRAPOLATE var [PRE mode] [POST mode] [DEFAULT value]
+//
+// where a mode is constant, cycle, cycle-offset, ping-pong or linear. For a
+// vec3 or colour var, each component gets the same settings.
+fn parse_cmd_extrapolate(rest: &str, var: &mut Var) -> Result<(), String> {
+    let mut rest = rest;
+    loop {
+        let (opt, after) = next_word(rest);
+        let (arg, after) = next_word(after);
+        rest = after;
+        let opt = opt.to_lowercase();
+        if opt.is_empty() { break; }
+        if arg.is_empty() { return Err(format!("{} needs a value", opt.to_uppercase())); }
+        let mode = || Ok(Some(match arg.to_lowercase().as_str() {
+            "constant" => Extrapolate::Constant,
+            "cycle" => Extrapolate::Cycle,
+            "cycle-offset" => Extrapolate::CycleOffset,
+            "ping-pong" => Extrapolate::PingPong,
+            "linear" => Extrapolate::Linear,
+            _ => return Err(format!("unknown extrapolation \"{}\"", arg)),
+        }));
+        match opt.as_str() {
+            "pre" => var.pre = mode()?,
+            "post" => var.post = mode()?,
+            "default" => var.default = Some(parse_n_floats(1, arg)?[0]),
+            _ => return Err(format!("unknown EXTRAPOLATE option \"{}\"", opt)),
+        }
+    }
+    Ok(())
+}
+
+// KEYFRAMES var [interpolation]
+//
+// Followed by one key per line, in any order, and closed by END:
+//
+//   time value [interpolation]
+//
+// where the interpolation, for the curve from that key to the next, is one
+// of linear, constant, catmull-rom, hermite slope [slope_out] or
+// tcb tension continuity bias. Keys without one use the block's, or linear.
+fn parse_keyframes_block(
+    lines: &mut SourceLines,
+    rest: &str,
+) -> Result<(String, Animation), String> {
+    let (var, interp) = next_word(rest);
+    if var.is_empty() { return Err("KEYFRAMES needs a var name".to_string()); }
+    let bad = |e: String| format!("keyframes for var \"{}\": {}", var, e);
+    let words: Vec<&str> = lexer::words(interp)?.iter().map(|w| w.text).collect();
+    let default = if words.is_empty() { Interp::Linear } else { Interp::parse(&words).map_err(bad)? };
+
+    let mut keys: Vec<Key> = vec![];
+    loop {
+        let line = lines.next().ok_or_else(|| bad(ran_out_of_lines("keyframes")))?
+            .map_err(|e| e.to_string())?;
+        let line = line.trim();
+        if line.is_empty() { continue; }
+        if line.eq_ignore_ascii_case("end") { break; }
+        let words = lexer::words(line).map_err(bad)?;
+        if words.len() < 2 { return Err(bad(format!("expected \"time value\", found \"{}\"", line))); }
+        let time = parse_word(line, &words[0]).map_err(bad)?;
+        let value = parse_word(line, &words[1]).map_err(bad)?;
+        let texts: Vec<&str> = words[2..].iter().map(|w| w.text).collect();
+        let interp = if texts.is_empty() { default } else { Interp::parse(&texts).map_err(bad)? };
+        keys.push(Key { time, value, interp });
+    }
+    if keys.is_empty() { return Err(bad("no keys".to_string())); }
+    keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+    if let Some(w) = keys.windows(2).find(|w| w[0].time == w[1].time) {
+        return Err(bad(format!("two keys at time {}", w[0].time)));
+    }
+    let (t1, t2) = (keys[0].time, keys[keys.len() - 1].time);
+    Ok((var.to_string(), Animation { t1, t2, motion: Motion::Keys(keys) }))
+}
+
+fn parse_n_u8s(n: usize, line: &str) -> Result<Vec<u8>, String> {
+    parse_n(n, line, "u8s")
+}
+
+fn parse_n_u32s(n: usize, line: &str) -> Result<Vec<u32>, String> {
+    parse_n(n, line, "integers")
+}
+
+fn parse_n_floats(n: usize, line: &str) -> Result<Vec<f32>, String> {
+    parse_n(n, line, "floats")
+}
+
+// Exactly `n` words of `line`, each parsed as a `T`, called `what` in errors.
+fn parse_n<T>(n: usize, line: &str, what: &str) -> Result<Vec<T>, String>
+where T: std::str::FromStr, T::Err: std::fmt::Display {
+    let xs = lexer::words(line)?.iter()
+        .map(|w| parse_word(line, w))
+        .collect::<Result<Vec<T>, _>>()?;
     if xs.len() == n {
         return Ok(xs);
     }
-    Err(format!("expected {} floats, found {}", n, xs.len()))
+    Err(format!("expected {} {}, found {}", n, what, xs.len()))
+}
+
+// `word` of `line` parsed as a `T`, or an error pointing at it.
+fn parse_word<T>(line: &str, word: &lexer::Word) -> Result<T, String>
+where T: std::str::FromStr, T::Err: std::fmt::Display {
+    word.text.parse().map_err(|e| {
+        expr::caret(line, word.start, word.end, &format!("parsing \"{}\": {}", word.text, e))
+    })
 }
 
+// Values are separated by spaces, so an expression with spaces in it needs
+// parentheses around it: `(sin(t) * 100 + 400)`.
 fn parse_n_vals(
     n: usize,
     line: &str,
 ) -> Result<Vec<Val>, String> {
-    let xs: Vec<Val> = line.split(" ")
-        .map(|s| s.parse()
-            .map_or(Val::Var(s.to_string()), |f| Val::Raw(f)))
-        .collect::<Vec<Val>>();
+    let xs = lexer::words(line)?.iter()
+        .map(|w| parse_val(w.text))
+        .collect::<Result<Vec<Val>, _>>()?;
     if xs.len() == n {
         return Ok(xs);
     }
     Err(format!("expected {} values, found {}", n, xs.len()))
 }
 
+// A number, a var name or an expression. Expressions that don't depend on
+// anything are worked out here.
+pub fn parse_val(s: &str) -> Result<Val, String> {
+    let e = expr::parse(s)?;
+    if let Some(x) = e.constant() { return Ok(Val::Raw(x)); }
+    Ok(match e {
+        Expr::Var(name) => Val::Var(name),
+        e => Val::Expr(Box::new(e)),
+    })
+}
+
+// Splits off the first word of `line`. See `lexer` for what makes a word.
+fn next_word(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    let end = lexer::word_end(line);
+    (&line[..end], line[end..].trim_start())
+}
+
+// Splits `line` after its first `n` words.
+fn split_words(n: usize, line: &str) -> (&str, &str) {
+    let line = line.trim();
+    let mut end = 0;
+    for _ in 0..n {
+        let start = line.len() - line[end..].trim_start().len();
+        end = start + lexer::word_end(&line[start..]);
+    }
+    (&line[..end], line[end..].trim_start())
+}
+
+// Splits `line` around its first " enclosed string, which may contain \" and
+// \\ escapes. Returns the text before the string, its unescaped contents and
+// the text after it.
+fn split_quoted(line: &str) -> Result<(&str, String, &str), String> {
+    let start = line.find('"').ok_or("expected \" enclosed string")?;
+    let mut s = String::new();
+    let mut chars = line[start+1..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((&line[..start], s, &line[start+1 + i+1..])),
+            '\\' => match chars.next() {
+                Some((_, c)) => s.push(c),
+                None => break,
+            },
+            _ => s.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
 // The output is wrapped in a Result to allow matching on errors
 // Returns an Iterator to the Reader of the lines of the file.
 fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
@@ -327,4 +1896,196 @@ where P: AsRef<Path>, {
 fn next(lines: &mut io::Lines<io::BufReader<File>>) -> Result<String, String> {
     lines.next().ok_or(ran_out_of_lines("line"))?
         .map_err(|e| e.to_string())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    fn dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("parser_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Loads `src` as a scene file called `name`, in a scratch dir.
+    fn load(name: &str, src: &str) -> Result<Scene, ParseErrors> {
+        let path = dir().join(name);
+        fs::write(&path, src).unwrap();
+        let scene = load_scene(&path.to_string_lossy());
+        fs::remove_file(&path).unwrap();
+        scene
+    }
+
+    #[test]
+    fn reports_every_bad_line_where_it_is() {
+        fs::write(dir().join("inc.scn"), "POINT 1 2\n").unwrap();
+        let src = concat!(
+            "POINT 0 0 0 1\n",
+            "FROB 1 2\n",
+            "COLOR 255 0 0 0 0\n",
+            "POINT 1 2 (3 * ) 4\n",
+            "\n",
+            "BEGIN g\n",
+            "  LINE 0 0 0 1 1 \"x\"\n",
+            "END\n",
+            "DEFINE m(a)\n",
+            "  POINT a 0 0\n",
+            "END\n",
+            "USE m(1)\n",
+            "INCLUDE \"inc.scn\"\n",
+            "TRIANGLE 1 2 3 4 5 6 7 8 (sin(t)*\\\n",
+            "  100\n",
+            "  100)\n",
+            "POINT\n",
+        );
+        let errors = match load("errors.scn", src) {
+            Ok(_) => panic!("loaded a bad scene"),
+            Err(ParseErrors(errors)) => errors,
+        };
+        fs::remove_file(dir().join("inc.scn")).unwrap();
+        let spans: Vec<_> = errors.iter().map(|e| {
+            let file = Path::new(&e.span.file).file_name().unwrap().to_string_lossy().into_owned();
+            (file, e.span.line, e.span.col, e.span.len)
+        }).collect();
+        let want = [
+            ("errors.scn", 2, 1, 4),
+            ("errors.scn", 3, 7, 11),
+            ("errors.scn", 4, 16, 1),
+            ("errors.scn", 7, 18, 1),
+            ("errors.scn", 10, 9, 5),
+            ("inc.scn", 1, 7, 3),
+            ("errors.scn", 14, 26, 1),
+            ("errors.scn", 17, 1, 5),
+        ];
+        assert_eq!(spans, want.map(|(f, line, col, len)| (f.to_string(), line, col, len)));
+
+        assert!(matches!(&errors[0].kind, ErrorKind::UnknownCommand(c) if c == "FROB"));
+        assert_eq!(errors[2].kind.to_string(), "expected a number, var or \"(\", found \")\"");
+        assert_eq!(errors[4].source, "  POINT a 0 0");
+        assert_eq!(errors[4].notes.len(), 1);
+        assert!(errors[4].notes[0].contains("USE m(1)"));
+        assert_eq!(errors[6].kind.to_string(), "unclosed \"(\"");
+        assert!(errors.iter().all(|e| !e.kind.to_string().contains('\n')));
+    }
+
+    #[test]
+    fn parameters_cant_be_animated() {
+        let src = concat!(
+            "ANIMATE v (0, 0, 0) (1, 1, 1) 0 1\n",
+            "ANIMATE k rgb(0, 0, 0) rgb(255, 255, 255) 0 1\n",
+            "DEFINE m(p, c, n)\n",
+            "  ANIMATE p (0, 0, 0) (1, 1, 1) 0 1\n",
+            "  EXTRAPOLATE c POST cycle\n",
+            "  ANIMATE n 0 1 0 1\n",
+            "  EXTRAPOLATE p.x POST cycle\n",
+            "  ANIMATE q 0 1 0 1\n",
+            "END\n",
+            "USE m(v, k, 2)\n",
+        );
+        let errors = match load("params.scn", src) {
+            Ok(_) => panic!("animated a parameter"),
+            Err(ParseErrors(errors)) => errors,
+        };
+        let msgs: Vec<_> = errors.iter().map(|e| (e.span.line, e.kind.to_string())).collect();
+        assert_eq!(msgs, [
+            (4, "\"p\" is a parameter and can't be animated".to_string()),
+            (5, "\"c\" is a parameter and can't be extrapolated".to_string()),
+            (6, "\"n\" is a parameter and can't be animated".to_string()),
+            (7, "\"p.x\" is a parameter and can't be extrapolated".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn commands_ignore_case_and_lines_continue() {
+        let scene = load("case.scn", concat!(
+            "point 1 2 3 4\n",
+            "Point 1 \\\n",
+            "  2 3 \\  # a comment\n",
+            "\t4\n",
+            "ANIMATE x 0 1 0 1 EASE-IN\n",
+            "extrapolate x post Cycle\n",
+        )).unwrap();
+        assert_eq!(scene.commands.len(), 2);
+        match &scene.commands[1] {
+            Command::Point { p, rad } => assert!(matches!(
+                (&p.x, &p.y, &p.z, rad), (Val::Raw(x), Val::Raw(y), Val::Raw(z), Val::Raw(r))
+                    if [*x, *y, *z, *r] == [1.0, 2.0, 3.0, 4.0])),
+            _ => panic!("expected a point"),
+        }
+        assert!(close(scene.var_at(1.5, "x").unwrap(), Easing::parse("ease-in").unwrap().apply(0.5)));
+    }
+
+    fn close(a: f32, b: f32) -> bool { (a - b).abs() < 1e-3 }
+
+    // x goes from 0 to 10 between t = 1 and 3, extrapolated with `modes`.
+    fn var(modes: &str) -> impl Fn(f32) -> f32 {
+        let src = format!("ANIMATE x 0 10 1 3\nEXTRAPOLATE x {}\n", modes);
+        let scene = load(&format!("{}.scn", modes.replace(' ', "_")), &src).unwrap();
+        move |t| scene.var_at(t, "x").unwrap()
+    }
+
+    #[test]
+    fn constant_holds_the_ends() {
+        let x = var("PRE constant POST constant");
+        for (t, want) in [(-5.0, 0.0), (1.0, 0.0), (2.0, 5.0), (3.0, 10.0), (9.0, 10.0)] {
+            assert!(close(x(t), want), "x({}) = {}", t, x(t));
+        }
+    }
+
+    #[test]
+    fn cycle_wraps_at_the_loop_boundaries() {
+        let x = var("PRE cycle POST cycle");
+        for (t, want) in [
+            (3.0, 10.0), (3.5, 2.5), (4.999, 9.995), (5.0, 0.0), (5.5, 2.5), (8.0, 5.0),
+            (0.5, 7.5), (-1.0, 0.0), (-1.001, 9.995),
+        ] {
+            assert!(close(x(t), want), "x({}) = {}", t, x(t));
+        }
+    }
+
+    #[test]
+    fn cycle_offset_carries_on_from_the_end() {
+        let x = var("PRE cycle-offset POST cycle-offset");
+        for (t, want) in [
+            (3.5, 12.5), (4.999, 19.995), (5.0, 20.0), (7.5, 32.5), (0.5, -2.5), (-1.0, -10.0),
+        ] {
+            assert!(close(x(t), want), "x({}) = {}", t, x(t));
+        }
+    }
+
+    #[test]
+    fn ping_pong_turns_at_the_loop_boundaries() {
+        let x = var("PRE ping-pong POST ping-pong");
+        for (t, want) in [
+            (3.5, 7.5), (4.5, 2.5), (4.999, 0.005), (5.0, 0.0), (5.5, 2.5), (7.0, 10.0),
+            (7.5, 7.5), (0.5, 2.5), (0.999, 0.005), (-1.0, 10.0), (-1.5, 7.5),
+        ] {
+            assert!(close(x(t), want), "x({}) = {}", t, x(t));
+        }
+    }
+
+    #[test]
+    fn linear_keeps_the_end_slopes() {
+        let x = var("PRE linear POST linear");
+        for (t, want) in [(4.0, 15.0), (5.0, 20.0), (0.0, -5.0), (-1.0, -10.0)] {
+            assert!(close(x(t), want), "x({}) = {}", t, x(t));
+        }
+        // An eased animation leaves with the slope it ends on.
+        let scene = load("eased.scn", "ANIMATE x 0 10 1 3 ease-in\nEXTRAPOLATE x POST linear\n")
+            .unwrap();
+        let slope = scene.var_at(4.0, "x").unwrap() - scene.var_at(3.0, "x").unwrap();
+        assert!(slope > 5.0, "slope {}", slope);
+    }
+
+    #[test]
+    fn vec3_components_share_the_settings() {
+        let scene = load("vec3.scn",
+            "ANIMATE p (0, 0, 0) (2, 4, 6) 0 1\nEXTRAPOLATE p POST cycle-offset\n")
+            .unwrap();
+        let p = |t| ["p.x", "p.y", "p.z"].map(|c| scene.var_at(t, c).unwrap());
+        assert_eq!(p(1.5), [3.0, 6.0, 9.0]);
+        assert!(scene.var_at(0.0, "p").unwrap_err().contains("is a vec3"));
+    }
+}
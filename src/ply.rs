@@ -0,0 +1,360 @@
+use crate::data::*;
+
+use std::fs;
+
+// Reads a PLY mesh or point cloud, in any of the three encodings. Vertices
+// may carry normals, colours and texture coordinates under their usual
+// property names; other properties and elements are read past and dropped.
+// Faces are triangulated as fans.
+pub fn load(path: &str) -> Result<Mesh, String> {
+    let bytes = fs::read(path)
+        .map_err(|_| format!("file \"{}\" does not exist", path))?;
+    parse(&bytes).map_err(|e| format!("bad PLY file \"{}\": {}", path, e))
+}
+
+// Writes `mesh` as little-endian binary PLY. Normals, texture coordinates
+// and colours are written when the mesh has them; a mesh without triangles
+// comes out as a point cloud.
+pub fn save(path: &str, mesh: &Mesh) -> Result<(), String> {
+    let mut header = format!("ply\nformat binary_little_endian 1.0\nelement vertex {}\n", mesh.points.len());
+    header += "property float x\nproperty float y\nproperty float z\n";
+    if mesh.normals.is_some() { header += "property float nx\nproperty float ny\nproperty float nz\n"; }
+    if mesh.uvs.is_some() { header += "property float u\nproperty float v\n"; }
+    if mesh.colors.is_some() { header += "property uchar red\nproperty uchar green\nproperty uchar blue\n"; }
+    header += &format!("element face {}\nproperty list uchar uint vertex_indices\nend_header\n",
+        mesh.triangles.len());
+
+    let mut out = header.into_bytes();
+    fn floats(out: &mut Vec<u8>, xs: &[f32]) {
+        for x in xs { out.extend_from_slice(&x.to_le_bytes()); }
+    }
+    for (i, p) in mesh.points.iter().enumerate() {
+        floats(&mut out, &[p.x, p.y, p.z]);
+        if let Some(n) = mesh.normals.as_ref().map(|n| n[i]) { floats(&mut out, &[n.x, n.y, n.z]); }
+        if let Some(uv) = mesh.uvs.as_ref().map(|uv| uv[i]) { floats(&mut out, &[uv.x, uv.y]); }
+        if let Some(c) = mesh.colors.as_ref().map(|c| c[i]) { out.extend_from_slice(&[c.r, c.g, c.b]); }
+    }
+    for t in &mesh.triangles {
+        out.push(3);
+        for v in t { out.extend_from_slice(&v.to_le_bytes()); }
+    }
+    fs::write(path, out).map_err(|e| format!("writing \"{}\": {}", path, e))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Type { I8, U8, I16, U16, I32, U32, F32, F64 }
+
+impl Type {
+    fn parse(s: &str) -> Result<Type, String> {
+        Ok(match s {
+            "char"   | "int8"    => Type::I8,
+            "uchar"  | "uint8"   => Type::U8,
+            "short"  | "int16"   => Type::I16,
+            "ushort" | "uint16"  => Type::U16,
+            "int"    | "int32"   => Type::I32,
+            "uint"   | "uint32"  => Type::U32,
+            "float"  | "float32" => Type::F32,
+            "double" | "float64" => Type::F64,
+            _ => return Err(format!("unknown property type \"{}\"", s)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::F32 => 4,
+            Type::F64 => 8,
+        }
+    }
+
+    fn is_float(self) -> bool {
+        self == Type::F32 || self == Type::F64
+    }
+}
+
+enum Property {
+    Scalar { name: String, ty: Type },
+    List { name: String, count: Type, item: Type },
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    props: Vec<Property>,
+}
+
+impl Element {
+    // The fewest bytes one item can take: a digit for each property in
+    // ASCII, or each scalar and list length in binary.
+    fn min_size(&self, format: Format) -> usize {
+        self.props.iter().map(|prop| match (format, prop) {
+            (Format::Ascii, _) => 1,
+            (_, Property::Scalar { ty, .. }) => ty.size(),
+            (_, Property::List { count, .. }) => count.size(),
+        }).sum()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format { Ascii, Little, Big }
+
+// Splits the header off and returns its elements, the format and where the
+// body starts.
+fn parse_header(bytes: &[u8]) -> Result<(Vec<Element>, Format, usize), String> {
+    if !bytes.starts_with(b"ply") { return Err("missing \"ply\" magic".to_string()); }
+
+    // Read a line at a time, so "end_header" in a comment doesn't end it.
+    let mut body = 0;
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    for i in 0.. {
+        let len = bytes[body..].iter().position(|&b| b == b'\n')
+            .ok_or("missing end_header")?;
+        let line = std::str::from_utf8(&bytes[body..body + len])
+            .map_err(|_| "header is not text".to_string())?;
+        body += len + 1;
+        if i == 0 { continue; }
+
+        let at = |e: String| format!("header line {}: {}", i + 1, e);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["end_header"] => break,
+            [] | ["comment", ..] | ["obj_info", ..] => {},
+            ["format", f, _version] => format = Some(match *f {
+                "ascii" => Format::Ascii,
+                "binary_little_endian" => Format::Little,
+                "binary_big_endian" => Format::Big,
+                _ => return Err(at(format!("unknown format \"{}\"", f))),
+            }),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|e| at(format!("parsing \"{}\": {}", count, e)))?,
+                props: vec![],
+            }),
+            ["property", "list", count, item, name] => {
+                let e = elements.last_mut().ok_or_else(|| at("property before element".to_string()))?;
+                e.props.push(Property::List {
+                    name: name.to_string(),
+                    count: Type::parse(count).map_err(at)?,
+                    item: Type::parse(item).map_err(at)?,
+                });
+            },
+            ["property", ty, name] => {
+                let e = elements.last_mut().ok_or_else(|| at("property before element".to_string()))?;
+                e.props.push(Property::Scalar {
+                    name: name.to_string(),
+                    ty: Type::parse(ty).map_err(at)?,
+                });
+            },
+            _ => return Err(at(format!("unexpected \"{}\"", line.trim()))),
+        }
+    }
+    let format = format.ok_or("missing format line")?;
+    Ok((elements, format, body))
+}
+
+// Reads values one at a time from the body, whatever its encoding.
+struct Body<'a> {
+    bytes: &'a [u8],
+    at: usize,
+    format: Format,
+}
+
+impl Body<'_> {
+    fn read(&mut self, ty: Type) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            while self.bytes.get(self.at).is_some_and(|c| c.is_ascii_whitespace()) {
+                self.at += 1;
+            }
+            let start = self.at;
+            while self.bytes.get(self.at).is_some_and(|c| !c.is_ascii_whitespace()) {
+                self.at += 1;
+            }
+            if start == self.at { return Err("unexpected end of file".to_string()); }
+            let word = std::str::from_utf8(&self.bytes[start..self.at]).unwrap_or("?");
+            return word.parse().map_err(|e| format!("parsing \"{}\": {}", word, e));
+        }
+
+        let b = self.bytes.get(self.at..self.at + ty.size())
+            .ok_or("unexpected end of file")?;
+        self.at += ty.size();
+        let mut buf = [0u8; 8];
+        buf[..b.len()].copy_from_slice(b);
+        if self.format == Format::Big { buf[..b.len()].reverse(); }
+        Ok(match ty {
+            Type::I8  => buf[0] as i8 as f64,
+            Type::U8  => buf[0] as f64,
+            Type::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+            Type::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+            Type::I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            Type::U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            Type::F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            Type::F64 => f64::from_le_bytes(buf),
+        })
+    }
+}
+
+fn parse(bytes: &[u8]) -> Result<Mesh, String> {
+    let (elements, format, start) = parse_header(bytes)?;
+    let mut body = Body { bytes, at: start, format };
+
+    let mut mesh = Mesh::new();
+    let (mut any_normals, mut any_uvs, mut any_colors) = (false, false, false);
+    let (mut normals, mut uvs, mut colors) = (vec![], vec![], vec![]);
+    let mut faces: Vec<Vec<f64>> = vec![];
+    for element in &elements {
+        // A count from the header is only believed if the rest of the file
+        // could hold that many.
+        if element.props.is_empty() && element.count > 0 {
+            return Err(format!("element \"{}\" has no properties", element.name));
+        }
+        let left = bytes.len() - body.at;
+        if element.count.checked_mul(element.min_size(format)).is_none_or(|size| size > left) {
+            return Err(format!("{} {} elements run past the end of the file", element.count, element.name));
+        }
+        for _ in 0..element.count {
+            let mut p = [0.0f32; 3];
+            let mut n = [0.0f32; 3];
+            let mut uv = [0.0f32; 2];
+            let mut rgb = [255u8; 3];
+            for prop in &element.props {
+                match prop {
+                    Property::Scalar { name, ty } => {
+                        let x = body.read(*ty)?;
+                        if element.name != "vertex" { continue; }
+                        // Float colours are in [0, 1], integer ones in [0, 255].
+                        let channel = |x: f64| {
+                            let x = if ty.is_float() { x * 255.0 } else { x };
+                            x.round().clamp(0.0, 255.0) as u8
+                        };
+                        match name.as_str() {
+                            "x" => p[0] = x as f32,
+                            "y" => p[1] = x as f32,
+                            "z" => p[2] = x as f32,
+                            "nx" => { n[0] = x as f32; any_normals = true; },
+                            "ny" => { n[1] = x as f32; any_normals = true; },
+                            "nz" => { n[2] = x as f32; any_normals = true; },
+                            "u" | "s" | "texture_u" | "texture_s" => { uv[0] = x as f32; any_uvs = true; },
+                            "v" | "t" | "texture_v" | "texture_t" => { uv[1] = x as f32; any_uvs = true; },
+                            "red" | "r" | "diffuse_red" => { rgb[0] = channel(x); any_colors = true; },
+                            "green" | "g" | "diffuse_green" => { rgb[1] = channel(x); any_colors = true; },
+                            "blue" | "b" | "diffuse_blue" => { rgb[2] = channel(x); any_colors = true; },
+                            _ => {},
+                        }
+                    },
+                    Property::List { name, count, item } => {
+                        let len = body.read(*count)?;
+                        if len < 0.0 { return Err(format!("negative list length {}", len)); }
+                        // Every item takes at least a byte, so this stops a
+                        // bad length from asking for more memory than we have.
+                        if len > (body.bytes.len() - body.at) as f64 {
+                            return Err(format!("list length {} runs past the end of the file", len));
+                        }
+                        let mut xs = Vec::with_capacity(len as usize);
+                        for _ in 0..len as usize {
+                            xs.push(body.read(*item)?);
+                        }
+                        if element.name == "face" && (name == "vertex_indices" || name == "vertex_index") {
+                            faces.push(xs);
+                        }
+                    },
+                }
+            }
+            if element.name == "vertex" {
+                mesh.points.push(Point3 { x: p[0], y: p[1], z: p[2] });
+                normals.push(Point3 { x: n[0], y: n[1], z: n[2] });
+                uvs.push(Point2 { x: uv[0], y: uv[1] });
+                colors.push(Color { r: rgb[0], g: rgb[1], b: rgb[2] });
+            }
+        }
+    }
+
+    for (i, face) in faces.iter().enumerate() {
+        if face.len() < 3 {
+            return Err(format!("face {} needs at least 3 corners, found {}", i, face.len()));
+        }
+        let n = mesh.points.len() as f64;
+        if let Some(&v) = face.iter().find(|&&v| v < 0.0 || v >= n) {
+            return Err(format!("face {} uses vertex {} out of range (have {})", i, v, n));
+        }
+        for j in 1..face.len() - 1 {
+            mesh.triangles.push([face[0] as u32, face[j] as u32, face[j + 1] as u32]);
+        }
+    }
+
+    if any_normals { mesh.normals = Some(normals); }
+    if any_uvs { mesh.uvs = Some(uvs); }
+    if any_colors { mesh.colors = Some(colors); }
+    mesh.validate()?;
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: &str = "\
+ply
+format ascii 1.0
+comment end_header in a comment isn't the end
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+";
+
+    #[test]
+    fn reads_ascii() {
+        let mesh = parse(SQUARE.as_bytes()).unwrap();
+        assert_eq!(mesh.points.len(), 4);
+        assert_eq!(mesh.triangles, [[0, 1, 2], [0, 2, 3]]);
+        let colors = mesh.colors.unwrap();
+        assert_eq!((colors[1].r, colors[1].g, colors[1].b), (0, 255, 0));
+        assert!(mesh.normals.is_none() && mesh.uvs.is_none());
+    }
+
+    #[test]
+    fn reads_binary() {
+        let mut bytes = b"ply\nformat binary_big_endian 1.0\nelement vertex 3\n\
+            property float x\nproperty float y\nproperty float z\n\
+            element face 1\nproperty list uchar ushort vertex_indices\nend_header\n".to_vec();
+        for x in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            bytes.extend_from_slice(&x.to_be_bytes());
+        }
+        bytes.push(3);
+        for i in [0u16, 1, 2] {
+            bytes.extend_from_slice(&i.to_be_bytes());
+        }
+        let mesh = parse(&bytes).unwrap();
+        assert_eq!(mesh.points[1].x, 1.0);
+        assert_eq!(mesh.triangles, [[0, 1, 2]]);
+        assert!(parse(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_counts() {
+        let err = |src: &str| parse(src.as_bytes()).err().unwrap();
+        let header = "ply\nformat ascii 1.0\n";
+        assert_eq!(err(&format!("{}element vertex 4294967295\nend_header\n", header)),
+            "element \"vertex\" has no properties");
+        assert_eq!(err(&format!("{}element vertex 4294967295\nproperty float x\nend_header\n1\n", header)),
+            "4294967295 vertex elements run past the end of the file");
+        assert!(err(&format!("{}element vertex 1\nproperty float x\nend_header\n", header))
+            .contains("past the end of the file"));
+        assert_eq!(err(&SQUARE.replace("4 0 1 2 3", "4 0 1 2 4")),
+            "face 0 uses vertex 4 out of range (have 4)");
+        assert_eq!(err(&SQUARE[..SQUARE.find("end_header\n0").unwrap()]), "missing end_header");
+    }
+}
@@ -0,0 +1,168 @@
+use crate::data::*;
+
+use std::f32::consts::PI;
+
+// Generators for the built-in solids. Each is centred on the origin and
+// about two units across, with round solids around the y axis; use SCALE and
+// friends to place them.
+
+fn p3(x: f32, y: f32, z: f32) -> Point3 {
+    Point3 { x, y, z }
+}
+
+// A `cols` by `rows` grid of quads over (u, v) in [0, 1], where `f` gives the
+// position and normal at each grid point.
+fn grid<F>(cols: usize, rows: usize, f: F) -> Mesh
+where F: Fn(f32, f32) -> (Point3, Point3) {
+    let mut mesh = Mesh::new();
+    let (mut normals, mut uvs) = (vec![], vec![]);
+    for j in 0..rows+1 {
+        for i in 0..cols+1 {
+            let (u, v) = (i as f32 / cols as f32, j as f32 / rows as f32);
+            let (p, n) = f(u, v);
+            mesh.points.push(p);
+            normals.push(n);
+            uvs.push(Point2 { x: u, y: v });
+        }
+    }
+    for j in 0..rows {
+        for i in 0..cols {
+            let a = (j * (cols + 1) + i) as u32;
+            let b = a + cols as u32 + 1;
+            mesh.triangles.push([a, b, a + 1]);
+            mesh.triangles.push([a + 1, b, b + 1]);
+        }
+    }
+    mesh.normals = Some(normals);
+    mesh.uvs = Some(uvs);
+    mesh.bounds = Bounds::of(&mesh.points);
+    mesh
+}
+
+// Turns each triangle to face the other way. `grid` winds its quads to face
+// the way u cross v points, which is inwards for solids whose surface runs
+// anticlockwise around y as u grows and downwards as v grows.
+fn flipped(mut mesh: Mesh) -> Mesh {
+    for tri in mesh.triangles.iter_mut() { tri.swap(1, 2); }
+    mesh
+}
+
+// A flat disc of radius 1 at height `y`, facing up or down.
+fn disc(segments: usize, y: f32, up: bool) -> Mesh {
+    let n = p3(0.0, if up { 1.0 } else { -1.0 }, 0.0);
+    let mut mesh = Mesh::new();
+    mesh.points.push(p3(0.0, y, 0.0));
+    let mut uvs = vec![Point2 { x: 0.5, y: 0.5 }];
+    for i in 0..segments+1 {
+        let a = 2.0 * PI * i as f32 / segments as f32;
+        mesh.points.push(p3(a.cos(), y, a.sin()));
+        uvs.push(Point2 { x: 0.5 + a.cos() / 2.0, y: 0.5 + a.sin() / 2.0 });
+    }
+    for i in 1..segments as u32 + 1 {
+        if up { mesh.triangles.push([0, i + 1, i]); }
+        else  { mesh.triangles.push([0, i, i + 1]); }
+    }
+    mesh.normals = Some(vec![n; mesh.points.len()]);
+    mesh.uvs = Some(uvs);
+    mesh.bounds = Bounds::of(&mesh.points);
+    mesh
+}
+
+pub fn sphere(segments: usize, rings: usize) -> Mesh {
+    grid(segments, rings, |u, v| {
+        let (lon, lat) = (2.0 * PI * u, PI * v);
+        let n = p3(lon.cos() * lat.sin(), -lat.cos(), lon.sin() * lat.sin());
+        (n, n)
+    })
+}
+
+// `segments` is the number of quads along each edge of each face.
+pub fn cube(segments: usize) -> Mesh {
+    // Normal, then the directions of u and v across the face.
+    let faces = [
+        (p3( 1.0, 0.0, 0.0), p3( 0.0, 0.0, -1.0), p3(0.0, -1.0, 0.0)),
+        (p3(-1.0, 0.0, 0.0), p3( 0.0, 0.0,  1.0), p3(0.0, -1.0, 0.0)),
+        (p3( 0.0, 1.0, 0.0), p3( 1.0, 0.0,  0.0), p3(0.0,  0.0, 1.0)),
+        (p3( 0.0,-1.0, 0.0), p3( 1.0, 0.0,  0.0), p3(0.0,  0.0,-1.0)),
+        (p3( 0.0, 0.0, 1.0), p3( 1.0, 0.0,  0.0), p3(0.0, -1.0, 0.0)),
+        (p3( 0.0, 0.0,-1.0), p3(-1.0, 0.0,  0.0), p3(0.0, -1.0, 0.0)),
+    ];
+    let mut mesh = Mesh::new();
+    for &(n, du, dv) in &faces {
+        mesh.append(grid(segments, segments, |u, v| {
+            (n + du * (2.0 * u - 1.0) + dv * (2.0 * v - 1.0), n)
+        }));
+    }
+    mesh
+}
+
+pub fn cylinder(segments: usize, rings: usize) -> Mesh {
+    let mut mesh = flipped(grid(segments, rings, |u, v| {
+        let a = 2.0 * PI * u;
+        (p3(a.cos(), 1.0 - 2.0 * v, a.sin()), p3(a.cos(), 0.0, a.sin()))
+    }));
+    mesh.append(disc(segments, 1.0, true));
+    mesh.append(disc(segments, -1.0, false));
+    mesh
+}
+
+// The apex is at the top; the base is a disc of radius 1.
+pub fn cone(segments: usize, rings: usize) -> Mesh {
+    // The side rises 2 for every 1 it moves in, so its normal is (2, 1) /
+    // sqrt(5) in the plane through the axis.
+    let slope = 1.0 / 5f32.sqrt();
+    let mut mesh = flipped(grid(segments, rings, |u, v| {
+        let a = 2.0 * PI * u;
+        (p3(a.cos() * v, 1.0 - 2.0 * v, a.sin() * v),
+         p3(a.cos() * 2.0 * slope, slope, a.sin() * 2.0 * slope))
+    }));
+    mesh.append(disc(segments, -1.0, false));
+    mesh
+}
+
+// A ring around the y axis. `minor` is the radius of the tube, which is
+// centred on the unit circle.
+pub fn torus(minor: f32, segments: usize, rings: usize) -> Mesh {
+    grid(segments, rings, |u, v| {
+        let (a, b) = (2.0 * PI * u, 2.0 * PI * v);
+        let n = p3(a.cos() * b.cos(), b.sin(), a.sin() * b.cos());
+        (p3(a.cos(), 0.0, a.sin()) + n * minor, n)
+    })
+}
+
+// A square in the xy plane, facing +z.
+pub fn plane(segments: usize, rings: usize) -> Mesh {
+    flipped(grid(segments, rings, |u, v| {
+        (p3(2.0 * u - 1.0, 2.0 * v - 1.0, 0.0), p3(0.0, 0.0, 1.0))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every triangle should wind anticlockwise seen from the side its
+    // normals point to, as export and NORMALS assume.
+    fn check_winding(name: &str, mesh: &Mesh) {
+        let normals = mesh.normals.as_ref().unwrap();
+        for i in 0..mesh.triangles.len() {
+            let [a, b, c] = mesh.triangle(i);
+            let (pa, pb, pc) = (mesh.points[a], mesh.points[b], mesh.points[c]);
+            let face = (pb - pa).cross(pc - pa);
+            // The poles of a sphere have triangles with no area.
+            if face.magnitude() < 1e-6 { continue; }
+            let n = normals[a] + normals[b] + normals[c];
+            assert!(face.dot(n) > 0.0, "{} triangle {} faces away from its normals", name, i);
+        }
+    }
+
+    #[test]
+    fn triangles_face_their_normals() {
+        check_winding("sphere", &sphere(8, 6));
+        check_winding("cube", &cube(2));
+        check_winding("cylinder", &cylinder(8, 2));
+        check_winding("cone", &cone(8, 2));
+        check_winding("torus", &torus(0.3, 8, 6));
+        check_winding("plane", &plane(2, 2));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::data::*;
+
+use std::collections::HashMap;
+use std::fs;
+
+// Reads an ASCII or binary STL file. STL stores every facet on its own, so
+// identical corners are welded into shared vertices, and each vertex's normal
+// is the average of the facet normals around it.
+pub fn load(path: &str) -> Result<Mesh, String> {
+    let bytes = fs::read(path)
+        .map_err(|_| format!("file \"{}\" does not exist", path))?;
+    parse(&bytes).map_err(|e| format!("bad STL file \"{}\": {}", path, e))
+}
+
+// Writes `mesh` as binary STL, with facet normals worked out from the
+// winding.
+pub fn save(path: &str, mesh: &Mesh) -> Result<(), String> {
+    let count = mesh.triangles.len();
+    let mut out = Vec::with_capacity(84 + count * 50);
+    let mut header = [0u8; 80];
+    let name = b"binary STL";
+    header[..name.len()].copy_from_slice(name);
+    out.extend_from_slice(&header);
+    out.extend_from_slice(&(count as u32).to_le_bytes());
+    for i in 0..count {
+        let [a, b, c] = mesh.triangle(i).map(|v| mesh.points[v]);
+        for p in [facet_normal(a, b, c), a, b, c] {
+            for x in [p.x, p.y, p.z] {
+                out.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&[0, 0]);
+    }
+    fs::write(path, out).map_err(|e| format!("writing \"{}\": {}", path, e))
+}
+
+// A facet's corners and the normal given for it.
+type Facet = ([Point3; 3], Point3);
+
+fn parse(bytes: &[u8]) -> Result<Mesh, String> {
+    // Binary files may also start with "solid", so trust the size first.
+    let binary = bytes.len() >= 84 && {
+        let n = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        bytes.len() == 84 + n * 50
+    };
+    let facets = if binary {
+        parse_binary(bytes)
+    } else if bytes.starts_with(b"solid") {
+        let src = std::str::from_utf8(bytes).map_err(|_| "not ASCII or binary STL".to_string())?;
+        parse_ascii(src)?
+    } else {
+        return Err("not ASCII or binary STL".to_string());
+    };
+    let mut mesh = weld(&facets);
+    mesh.validate()?;
+    Ok(mesh)
+}
+
+fn parse_binary(bytes: &[u8]) -> Vec<Facet> {
+    let f = |at: usize| f32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    let p = |at: usize| Point3 { x: f(at), y: f(at + 4), z: f(at + 8) };
+    (84..bytes.len()).step_by(50)
+        .map(|at| ([p(at + 12), p(at + 24), p(at + 36)], p(at)))
+        .collect()
+}
+
+fn parse_ascii(src: &str) -> Result<Vec<Facet>, String> {
+    let mut facets = vec![];
+    let mut normal = Point3 { x: 0.0, y: 0.0, z: 0.0 };
+    let mut corners: Vec<Point3> = vec![];
+    for (i, line) in src.lines().enumerate() {
+        let at = |e: String| format!("line {}: {}", i + 1, e);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["facet", "normal", xs @ ..] => {
+                normal = point(xs).map_err(at)?;
+                corners.clear();
+            },
+            ["vertex", xs @ ..] => corners.push(point(xs).map_err(at)?),
+            ["endfacet"] => {
+                if corners.len() < 3 {
+                    return Err(at(format!("facet needs 3 vertices, found {}", corners.len())));
+                }
+                // Some writers put polygons in a facet; split them into fans.
+                for j in 1..corners.len() - 1 {
+                    facets.push(([corners[0], corners[j], corners[j + 1]], normal));
+                }
+            },
+            _ => {},
+        }
+    }
+    Ok(facets)
+}
+
+fn point(xs: &[&str]) -> Result<Point3, String> {
+    if xs.len() != 3 { return Err(format!("expected 3 numbers, found {}", xs.len())); }
+    let f = |s: &str| s.parse::<f32>().map_err(|e| format!("parsing \"{}\": {}", s, e));
+    Ok(Point3 { x: f(xs[0])?, y: f(xs[1])?, z: f(xs[2])? })
+}
+
+fn weld(facets: &[Facet]) -> Mesh {
+    let mut mesh = Mesh::new();
+    let mut normals: Vec<Point3> = vec![];
+    let mut index: HashMap<[u32; 3], u32> = HashMap::new();
+    for &(corners, normal) in facets {
+        // Writers often leave the normal as zero.
+        let len = normal.magnitude();
+        let n = if len > 0.0 { normal * (1.0 / len) } else {
+            facet_normal(corners[0], corners[1], corners[2])
+        };
+        let tri = corners.map(|p| {
+            // Adding zero turns -0.0 into 0.0, so they weld together.
+            let key = [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()];
+            let i = *index.entry(key).or_insert_with(|| {
+                mesh.points.push(p);
+                normals.push(Point3 { x: 0.0, y: 0.0, z: 0.0 });
+                mesh.points.len() as u32 - 1
+            });
+            normals[i as usize] = normals[i as usize] + n;
+            i
+        });
+        mesh.triangles.push(tri);
+    }
+    for n in normals.iter_mut() {
+        let len = n.magnitude();
+        if len > 0.0 { *n = *n * (1.0 / len); }
+    }
+    mesh.normals = Some(normals);
+    mesh
+}
+
+fn facet_normal(a: Point3, b: Point3, c: Point3) -> Point3 {
+    let (u, v) = (b - a, c - a);
+    let n = Point3 {
+        x: u.y * v.z - u.z * v.y,
+        y: u.z * v.x - u.x * v.z,
+        z: u.x * v.y - u.y * v.x,
+    };
+    let len = n.magnitude();
+    if len > 0.0 { n * (1.0 / len) } else { n }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two facets of a unit square in the xy plane, sharing an edge.
+    const SQUARE: &str = "\
+solid square
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 1 1 0
+    endloop
+  endfacet
+  facet normal 0 0 0
+    outer loop
+      vertex 0 0 -0
+      vertex 1 1 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid square
+";
+
+    #[test]
+    fn reads_ascii_and_welds() {
+        let mesh = parse(SQUARE.as_bytes()).unwrap();
+        assert_eq!(mesh.points.len(), 4);
+        assert_eq!(mesh.triangles, [[0, 1, 2], [0, 2, 3]]);
+        // The zero normal is worked out from the winding.
+        for n in mesh.normals.unwrap() {
+            assert_eq!((n.x, n.y, n.z), (0.0, 0.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn reads_binary() {
+        let mut bytes = b"solid but really binary".to_vec();
+        bytes.resize(80, 0);
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        for x in [0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0, 0]);
+        let mesh = parse(&bytes).unwrap();
+        assert_eq!((mesh.points.len(), mesh.triangles.len()), (3, 1));
+        assert_eq!(mesh.points[1].x, 1.0);
+        // Cut short, it's neither binary nor good ASCII.
+        assert!(parse(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_facets() {
+        let err = |src: &str| parse(src.as_bytes()).err().unwrap();
+        assert_eq!(err(&SQUARE.replace("vertex 1 0 0", "vertex 1 0")), "line 5: expected 3 numbers, found 2");
+        assert_eq!(err(&SQUARE.replace("      vertex 1 1 0\n    endloop\n  endfacet\n  facet", "    endloop\n  endfacet\n  facet")),
+            "line 7: facet needs 3 vertices, found 2");
+        assert_eq!(err("not an stl"), "not ASCII or binary STL");
+    }
+}
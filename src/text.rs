@@ -0,0 +1,485 @@
+use crate::data::*;
+use crate::draw::{self, Screen};
+use crate::paint::Paint;
+use crate::parser::{self, Eval, Scene, Val};
+
+use std::collections::HashMap;
+use std::fs;
+
+#[derive(Debug, Clone)]
+struct Glyph {
+    // Bounding box of the bitmap, in font pixels. The offsets are measured
+    // from the pen position on the baseline, with y pointing up (as in BDF).
+    w: usize,
+    h: usize,
+    x_off: isize,
+    y_off: isize,
+    advance: isize,
+    // Row-major, top row first.
+    bits: Vec<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Font {
+    ascent: isize,
+    descent: isize,
+    glyphs: HashMap<char, Glyph>,
+    default: Option<char>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HAlign { Left, Center, Right }
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VAlign { Top, Middle, Baseline, Bottom }
+
+#[derive(Debug, Copy, Clone)]
+pub struct Align {
+    pub h: HAlign,
+    pub v: VAlign,
+}
+
+impl Align {
+    pub const DEFAULT: Align = Align { h: HAlign::Left, v: VAlign::Top };
+}
+
+impl Font {
+    // The embedded 8x8 font. Covers printable ASCII.
+    pub fn builtin() -> Font {
+        let mut glyphs = HashMap::new();
+        for (i, rows) in FONT_8X8.iter().enumerate() {
+            let c = (0x20 + i as u8) as char;
+            let mut bits = Vec::with_capacity(64);
+            for row in rows {
+                for col in 0..8 {
+                    // Bit 0 is the leftmost pixel.
+                    bits.push(row & (1 << col) != 0);
+                }
+            }
+            glyphs.insert(c, Glyph {
+                w: 8, h: 8, x_off: 0, y_off: -1, advance: 8, bits,
+            });
+        }
+        Font { ascent: 7, descent: 1, glyphs, default: Some('?') }
+    }
+
+    pub fn load_bdf(path: &str) -> Result<Font, String> {
+        let src = fs::read_to_string(path)
+            .map_err(|_| format!("file \"{}\" does not exist", path))?;
+        parse_bdf(&src).map_err(|e| format!("bad BDF font \"{}\": {}", path, e))
+    }
+
+    pub fn height(&self) -> isize {
+        self.ascent + self.descent
+    }
+
+    fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c)
+            .or_else(|| self.default.and_then(|d| self.glyphs.get(&d)))
+    }
+
+    // Width of `s` in font pixels.
+    pub fn width(&self, s: &str) -> isize {
+        s.chars().map(|c| self.glyph(c).map_or(0, |g| g.advance)).sum()
+    }
+}
+
+fn parse_bdf(src: &str) -> Result<Font, String> {
+    let mut glyphs = HashMap::new();
+    let mut bbox = (0, 0, 0, 0);
+    let mut ascent = None;
+    let mut descent = None;
+    let mut default_code = None;
+
+    let mut lines = src.lines();
+    while let Some(line) = lines.next() {
+        let mut words = line.split_whitespace();
+        let key = match words.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let args = words.collect::<Vec<&str>>();
+        match key {
+            "FONTBOUNDINGBOX" => {
+                let xs = parse_ints(4, &args)?;
+                check_glyph_size(xs[0], xs[1])?;
+                bbox = (xs[0], xs[1], xs[2], xs[3]);
+            },
+            "FONT_ASCENT"  => ascent = Some(parse_ints(1, &args)?[0]),
+            "FONT_DESCENT" => descent = Some(parse_ints(1, &args)?[0]),
+            "DEFAULT_CHAR" => default_code = Some(parse_ints(1, &args)?[0]),
+            "STARTCHAR" => {
+                if let Some((c, glyph)) = parse_bdf_char(&mut lines, bbox)? {
+                    glyphs.insert(c, glyph);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    if glyphs.is_empty() { return Err("no glyphs".to_string()); }
+    let default = default_code
+        .and_then(|c| std::char::from_u32(c as u32))
+        .filter(|c| glyphs.contains_key(c))
+        .or(Some('?'));
+    Ok(Font {
+        ascent: ascent.unwrap_or(bbox.1 + bbox.3),
+        descent: descent.unwrap_or(-bbox.3),
+        glyphs,
+        default,
+    })
+}
+
+// Parses the body of a glyph, up to and including ENDCHAR. Returns None for
+// glyphs that have no Unicode encoding.
+fn parse_bdf_char<'a, I>(
+    lines: &mut I,
+    bbox: (isize, isize, isize, isize),
+) -> Result<Option<(char, Glyph)>, String>
+where I: Iterator<Item = &'a str> {
+    let mut code = -1;
+    let mut advance = bbox.0;
+    let (mut w, mut h, mut x_off, mut y_off) = bbox;
+    let mut bits = vec![];
+
+    loop {
+        let line = lines.next().ok_or("unterminated glyph")?;
+        let mut words = line.split_whitespace();
+        let key = words.next();
+        let args = words.collect::<Vec<&str>>();
+        match key {
+            // A second, non-standard code may follow; only the first matters.
+            Some("ENCODING") => code = parse_ints(1, &args[..args.len().min(1)])?[0],
+            Some("DWIDTH")   => advance = parse_ints(2, &args)?[0],
+            Some("BBX") => {
+                let xs = parse_ints(4, &args)?;
+                check_glyph_size(xs[0], xs[1])?;
+                w = xs[0]; h = xs[1]; x_off = xs[2]; y_off = xs[3];
+            },
+            Some("BITMAP") => {
+                for _ in 0..h {
+                    let row = lines.next().ok_or("unterminated bitmap")?.trim();
+                    if !row.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return Err(format!("bad bitmap row \"{}\": expected hex digits", row));
+                    }
+                    let bytes = (0..row.len() / 2)
+                        .map(|i| u8::from_str_radix(&row[i*2..i*2 + 2], 16).unwrap())
+                        .collect::<Vec<u8>>();
+                    for col in 0..w as usize {
+                        // Most significant bit is the leftmost pixel.
+                        let byte = bytes.get(col / 8).copied().unwrap_or(0);
+                        bits.push(byte & (0x80 >> (col % 8)) != 0);
+                    }
+                }
+            },
+            Some("ENDCHAR") => break,
+            _ => {},
+        }
+    }
+
+    let c = match std::char::from_u32(code as u32) {
+        Some(c) if code >= 0 => c,
+        _ => return Ok(None),
+    };
+    bits.resize((w * h) as usize, false);
+    Ok(Some((c, Glyph {
+        w: w as usize, h: h as usize, x_off, y_off,
+        advance, bits,
+    })))
+}
+
+// Glyphs are drawn pixel by pixel, so anything much bigger than a screen is
+// a broken file.
+const MAX_GLYPH_SIZE: isize = 1024;
+
+fn check_glyph_size(w: isize, h: isize) -> Result<(), String> {
+    if w < 0 || h < 0 {
+        return Err("negative glyph size".to_string());
+    }
+    if w > MAX_GLYPH_SIZE || h > MAX_GLYPH_SIZE {
+        return Err(format!("glyph size {}x{} is over the limit of {}", w, h, MAX_GLYPH_SIZE));
+    }
+    Ok(())
+}
+
+fn parse_ints(n: usize, args: &[&str]) -> Result<Vec<isize>, String> {
+    if args.len() != n {
+        return Err(format!("expected {} ints, found {}", n, args.len()));
+    }
+    args.iter()
+        .map(|s| s.parse().map_err(|e| format!("parsing \"{}\": {}", s, e)))
+        .collect()
+}
+
+// Draws `s` with its alignment point at `at`. `size` is the height of a line
+// in screen pixels; glyphs are scaled up from the font with nearest sampling.
+pub fn draw_text(
+    screen: &mut Screen,
+    font: &Font,
+    s: &str,
+    at: PointScreen,
+    size: f32,
+    align: Align,
+    paint: &Paint,
+) {
+    let scale = size / font.height() as f32;
+    if scale <= 0.0 { return; }
+
+    let width = font.width(s) as f32 * scale;
+    let left = at.x as f32 - match align.h {
+        HAlign::Left   => 0.0,
+        HAlign::Center => width / 2.0,
+        HAlign::Right  => width,
+    };
+    let baseline = at.y as f32 + match align.v {
+        VAlign::Top      => font.ascent as f32 * scale,
+        VAlign::Middle   => (font.ascent - font.descent) as f32 * scale / 2.0,
+        VAlign::Baseline => 0.0,
+        VAlign::Bottom   => -font.descent as f32 * scale,
+    };
+
+    // Positions are kept in font pixels and only scaled when filling, so
+    // rounding doesn't accumulate across the string.
+    let mut pen = 0;
+    for c in s.chars() {
+        let glyph = match font.glyph(c) {
+            Some(g) => g,
+            None => continue,
+        };
+        let top = -(glyph.y_off + glyph.h as isize);
+        for row in 0..glyph.h {
+            for col in 0..glyph.w {
+                if !glyph.bits[row * glyph.w + col] { continue; }
+                let fx = pen + glyph.x_off + col as isize;
+                let fy = top + row as isize;
+                draw::fill_rect(
+                    screen,
+                    PointScreen {
+                        x: (left + fx as f32 * scale) as isize,
+                        y: (baseline + fy as f32 * scale) as isize,
+                    },
+                    PointScreen {
+                        x: (left + (fx + 1) as f32 * scale) as isize - 1,
+                        y: (baseline + (fy + 1) as f32 * scale) as isize - 1,
+                    },
+                    paint);
+            }
+        }
+        pen += glyph.advance;
+    }
+}
+
+// ====================================================================== //
+// ============================== TEMPLATES ============================= //
+// ====================================================================== //
+
+// A piece of a TEXT string. Values are written as `{var}`, `{var:.2}` or
+// `{var:8.2}` (width and precision, as in `format!`). `{{` and `}}` escape
+// literal braces.
+#[derive(Debug, Clone)]
+pub enum TextPart {
+    Lit(String),
+    Val { val: Val, width: usize, precision: Option<usize> },
+}
+
+pub fn parse_template(s: &str) -> Result<Vec<TextPart>, String> {
+    let mut parts = vec![];
+    let mut lit = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => { chars.next(); lit.push('{'); },
+            '}' if chars.peek() == Some(&'}') => { chars.next(); lit.push('}'); },
+            '}' => return Err(format!("unmatched '}}' in \"{}\"", s)),
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err(format!("unclosed '{{' in \"{}\"", s)),
+                    }
+                }
+                if !lit.is_empty() {
+                    parts.push(TextPart::Lit(lit));
+                    lit = String::new();
+                }
+                parts.push(parse_spec(&spec)?);
+            },
+            _ => lit.push(c),
+        }
+    }
+    if !lit.is_empty() { parts.push(TextPart::Lit(lit)); }
+    Ok(parts)
+}
+
+fn parse_spec(spec: &str) -> Result<TextPart, String> {
+    let (name, fmt) = spec.split_once(':').unwrap_or((spec, ""));
+    let name = name.trim();
+    if name.is_empty() { return Err("empty {} in text".to_string()); }
+    let val = parser::parse_val(name)?;
+
+    let bad_fmt = || format!("bad format \"{}\" for \"{}\"", fmt, name);
+    let (width, precision) = fmt.split_once('.').unwrap_or((fmt, ""));
+    let width = if width.is_empty() { 0 }
+        else { width.parse().map_err(|_| bad_fmt())? };
+    let precision = if fmt.contains('.') {
+        Some(precision.parse().map_err(|_| bad_fmt())?)
+    } else { None };
+
+    Ok(TextPart::Val { val, width, precision })
+}
+
+pub fn eval_template(
+    parts: &[TextPart],
+    t: f32,
+    scene: &Scene
+) -> Result<String, String> {
+    let mut s = String::new();
+    for part in parts {
+        match part {
+            TextPart::Lit(lit) => s.push_str(lit),
+            TextPart::Val { val, width, precision } => {
+                let x = val.eval_at(t, scene)?;
+                match precision {
+                    Some(p) => s.push_str(&format!("{:w$.p$}", x, w = width, p = p)),
+                    None    => s.push_str(&format!("{:w$}", x, w = width)),
+                }
+            },
+        }
+    }
+    Ok(s)
+}
+
+// font8x8_basic by Daniel Hepper (public domain), U+0020 to U+007F.
+// One byte per row, top row first; bit 0 is the leftmost pixel.
+const FONT_8X8: [[u8; 8]; 96] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x18, 0x3C, 0x3C, 0x18, 0x18, 0x00, 0x18, 0x00], // '!'
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x36, 0x36, 0x7F, 0x36, 0x7F, 0x36, 0x36, 0x00], // '#'
+    [0x0C, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x0C, 0x00], // '$'
+    [0x00, 0x63, 0x33, 0x18, 0x0C, 0x66, 0x63, 0x00], // '%'
+    [0x1C, 0x36, 0x1C, 0x6E, 0x3B, 0x33, 0x6E, 0x00], // '&'
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // '''
+    [0x18, 0x0C, 0x06, 0x06, 0x06, 0x0C, 0x18, 0x00], // '('
+    [0x06, 0x0C, 0x18, 0x18, 0x18, 0x0C, 0x06, 0x00], // ')'
+    [0x00, 0x66, 0x3C, 0xFF, 0x3C, 0x66, 0x00, 0x00], // '*'
+    [0x00, 0x0C, 0x0C, 0x3F, 0x0C, 0x0C, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ','
+    [0x00, 0x00, 0x00, 0x3F, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00], // '.'
+    [0x60, 0x30, 0x18, 0x0C, 0x06, 0x03, 0x01, 0x00], // '/'
+    [0x3E, 0x63, 0x73, 0x7B, 0x6F, 0x67, 0x3E, 0x00], // '0'
+    [0x0C, 0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x3F, 0x00], // '1'
+    [0x1E, 0x33, 0x30, 0x1C, 0x06, 0x33, 0x3F, 0x00], // '2'
+    [0x1E, 0x33, 0x30, 0x1C, 0x30, 0x33, 0x1E, 0x00], // '3'
+    [0x38, 0x3C, 0x36, 0x33, 0x7F, 0x30, 0x78, 0x00], // '4'
+    [0x3F, 0x03, 0x1F, 0x30, 0x30, 0x33, 0x1E, 0x00], // '5'
+    [0x1C, 0x06, 0x03, 0x1F, 0x33, 0x33, 0x1E, 0x00], // '6'
+    [0x3F, 0x33, 0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x00], // '7'
+    [0x1E, 0x33, 0x33, 0x1E, 0x33, 0x33, 0x1E, 0x00], // '8'
+    [0x1E, 0x33, 0x33, 0x3E, 0x30, 0x18, 0x0E, 0x00], // '9'
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x00], // ':'
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ';'
+    [0x18, 0x0C, 0x06, 0x03, 0x06, 0x0C, 0x18, 0x00], // '<'
+    [0x00, 0x00, 0x3F, 0x00, 0x00, 0x3F, 0x00, 0x00], // '='
+    [0x06, 0x0C, 0x18, 0x30, 0x18, 0x0C, 0x06, 0x00], // '>'
+    [0x1E, 0x33, 0x30, 0x18, 0x0C, 0x00, 0x0C, 0x00], // '?'
+    [0x3E, 0x63, 0x7B, 0x7B, 0x7B, 0x03, 0x1E, 0x00], // '@'
+    [0x0C, 0x1E, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x00], // 'A'
+    [0x3F, 0x66, 0x66, 0x3E, 0x66, 0x66, 0x3F, 0x00], // 'B'
+    [0x3C, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3C, 0x00], // 'C'
+    [0x1F, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1F, 0x00], // 'D'
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x46, 0x7F, 0x00], // 'E'
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x06, 0x0F, 0x00], // 'F'
+    [0x3C, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7C, 0x00], // 'G'
+    [0x33, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x33, 0x00], // 'H'
+    [0x1E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'I'
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E, 0x00], // 'J'
+    [0x67, 0x66, 0x36, 0x1E, 0x36, 0x66, 0x67, 0x00], // 'K'
+    [0x0F, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7F, 0x00], // 'L'
+    [0x63, 0x77, 0x7F, 0x7F, 0x6B, 0x63, 0x63, 0x00], // 'M'
+    [0x63, 0x67, 0x6F, 0x7B, 0x73, 0x63, 0x63, 0x00], // 'N'
+    [0x1C, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1C, 0x00], // 'O'
+    [0x3F, 0x66, 0x66, 0x3E, 0x06, 0x06, 0x0F, 0x00], // 'P'
+    [0x1E, 0x33, 0x33, 0x33, 0x3B, 0x1E, 0x38, 0x00], // 'Q'
+    [0x3F, 0x66, 0x66, 0x3E, 0x36, 0x66, 0x67, 0x00], // 'R'
+    [0x1E, 0x33, 0x07, 0x0E, 0x38, 0x33, 0x1E, 0x00], // 'S'
+    [0x3F, 0x2D, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'T'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3F, 0x00], // 'U'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // 'V'
+    [0x63, 0x63, 0x63, 0x6B, 0x7F, 0x77, 0x63, 0x00], // 'W'
+    [0x63, 0x63, 0x36, 0x1C, 0x1C, 0x36, 0x63, 0x00], // 'X'
+    [0x33, 0x33, 0x33, 0x1E, 0x0C, 0x0C, 0x1E, 0x00], // 'Y'
+    [0x7F, 0x63, 0x31, 0x18, 0x4C, 0x66, 0x7F, 0x00], // 'Z'
+    [0x1E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1E, 0x00], // '['
+    [0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x40, 0x00], // '\'
+    [0x1E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1E, 0x00], // ']'
+    [0x08, 0x1C, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF], // '_'
+    [0x0C, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x1E, 0x30, 0x3E, 0x33, 0x6E, 0x00], // 'a'
+    [0x07, 0x06, 0x06, 0x3E, 0x66, 0x66, 0x3B, 0x00], // 'b'
+    [0x00, 0x00, 0x1E, 0x33, 0x03, 0x33, 0x1E, 0x00], // 'c'
+    [0x38, 0x30, 0x30, 0x3E, 0x33, 0x33, 0x6E, 0x00], // 'd'
+    [0x00, 0x00, 0x1E, 0x33, 0x3F, 0x03, 0x1E, 0x00], // 'e'
+    [0x1C, 0x36, 0x06, 0x0F, 0x06, 0x06, 0x0F, 0x00], // 'f'
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x1F], // 'g'
+    [0x07, 0x06, 0x36, 0x6E, 0x66, 0x66, 0x67, 0x00], // 'h'
+    [0x0C, 0x00, 0x0E, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'i'
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E], // 'j'
+    [0x07, 0x06, 0x66, 0x36, 0x1E, 0x36, 0x67, 0x00], // 'k'
+    [0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'l'
+    [0x00, 0x00, 0x33, 0x7F, 0x7F, 0x6B, 0x63, 0x00], // 'm'
+    [0x00, 0x00, 0x1F, 0x33, 0x33, 0x33, 0x33, 0x00], // 'n'
+    [0x00, 0x00, 0x1E, 0x33, 0x33, 0x33, 0x1E, 0x00], // 'o'
+    [0x00, 0x00, 0x3B, 0x66, 0x66, 0x3E, 0x06, 0x0F], // 'p'
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x78], // 'q'
+    [0x00, 0x00, 0x3B, 0x6E, 0x66, 0x06, 0x0F, 0x00], // 'r'
+    [0x00, 0x00, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x00], // 's'
+    [0x08, 0x0C, 0x3E, 0x0C, 0x0C, 0x2C, 0x18, 0x00], // 't'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6E, 0x00], // 'u'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // 'v'
+    [0x00, 0x00, 0x63, 0x6B, 0x7F, 0x7F, 0x36, 0x00], // 'w'
+    [0x00, 0x00, 0x63, 0x36, 0x1C, 0x36, 0x63, 0x00], // 'x'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3E, 0x30, 0x1F], // 'y'
+    [0x00, 0x00, 0x3F, 0x19, 0x0C, 0x26, 0x3F, 0x00], // 'z'
+    [0x38, 0x0C, 0x0C, 0x07, 0x0C, 0x0C, 0x38, 0x00], // '{'
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], // '|'
+    [0x07, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0x07, 0x00], // '}'
+    [0x6E, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // DEL
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bdf(bbx: &str, rows: &[&str]) -> String {
+        format!(
+            "STARTFONT 2.1\nFONTBOUNDINGBOX 8 2 0 0\nSTARTCHAR A\nENCODING 65\n\
+             DWIDTH 8 0\nBBX {}\nBITMAP\n{}\nENDCHAR\nENDFONT\n",
+            bbx, rows.join("\n"))
+    }
+
+    #[test]
+    fn reads_glyphs() {
+        let font = parse_bdf(&bdf("8 2 0 0", &["81", "FF"])).unwrap();
+        let g = font.glyph('A').unwrap();
+        assert_eq!((g.w, g.h, g.advance), (8, 2, 8));
+        let row = |y: usize| g.bits[y*8..y*8 + 8].iter().filter(|&&b| b).count();
+        assert_eq!((row(0), row(1)), (2, 8));
+        assert!(g.bits[0] && g.bits[7]);
+    }
+
+    #[test]
+    fn rejects_bad_bitmaps() {
+        // Not hex, and bytes that aren't ASCII.
+        assert!(parse_bdf(&bdf("8 2 0 0", &["8Z", "FF"])).is_err());
+        assert!(parse_bdf(&bdf("8 2 0 0", &["aéb", "FF"])).is_err());
+        assert!(parse_bdf(&bdf("8 2 0 0", &["81"])).is_err());
+        assert!(parse_bdf(&bdf("60000 60000 0 0", &["81", "FF"])).is_err());
+        assert!(parse_bdf(&bdf("-1 2 0 0", &["81", "FF"])).is_err());
+    }
+}
@@ -61,6 +61,51 @@ impl Transform {
         basis_change * rot_around_z * basis_change.transpose()
     }
 
+    // Rotation by the unit quaternion xi + yj + zk + w.
+    pub fn quaternion(x: f32, y: f32, z: f32, w: f32) -> Transform {
+        Transform {
+            data: [
+                1.0 - 2.0*(y*y + z*z), 2.0*(x*y - z*w),       2.0*(x*z + y*w),       0.0,
+                2.0*(x*y + z*w),       1.0 - 2.0*(x*x + z*z), 2.0*(y*z - x*w),       0.0,
+                2.0*(x*z - y*w),       2.0*(y*z + x*w),       1.0 - 2.0*(x*x + y*y), 0.0,
+                0.0,                   0.0,                   0.0,                   1.0,
+            ]
+        }
+    }
+
+    // From a matrix stored column by column, as glTF and OpenGL do.
+    pub fn from_columns(m: [f32; 16]) -> Transform {
+        Transform { data: m }.transpose()
+    }
+
+    // Of the upper-left 3x3. Negative when the transform mirrors, which
+    // turns triangle windings inside out.
+    pub fn determinant(&self) -> f32 {
+        let d = self.data;
+        d[0] * (d[5]*d[10] - d[6]*d[9])
+            - d[1] * (d[4]*d[10] - d[6]*d[8])
+            + d[2] * (d[4]*d[9] - d[5]*d[8])
+    }
+
+    // Takes a surface normal through the transform. That needs the inverse
+    // transpose of the upper-left 3x3; its cofactor matrix is the same up to
+    // scale, which normalising removes.
+    pub fn normal(&self, n: Point3) -> Point3 {
+        let d = self.data;
+        let c = [
+            d[5]*d[10] - d[6]*d[9], d[6]*d[8] - d[4]*d[10], d[4]*d[9] - d[5]*d[8],
+            d[2]*d[9] - d[1]*d[10], d[0]*d[10] - d[2]*d[8], d[1]*d[8] - d[0]*d[9],
+            d[1]*d[6] - d[2]*d[5],  d[2]*d[4] - d[0]*d[6],  d[0]*d[5] - d[1]*d[4],
+        ];
+        let out = Point3 {
+            x: c[0]*n.x + c[1]*n.y + c[2]*n.z,
+            y: c[3]*n.x + c[4]*n.y + c[5]*n.z,
+            z: c[6]*n.x + c[7]*n.y + c[8]*n.z,
+        };
+        let len = out.magnitude() * self.determinant().signum();
+        if len == 0.0 { out } else { out * (1.0 / len) }
+    }
+
     pub fn transpose(&self) -> Transform {
         let d = self.data;
         Transform { data: [